@@ -0,0 +1,45 @@
+#!/bin/bash
+# 基準のバイナリと今のビルドを tools/in の先頭 n 個の seed で比べる。
+# 1 つの seed のスコアは高さマップのサンプルの引き次第で数倍ぶれるので、
+# ratio 倍より悪くなった seed は一覧に出すだけにして、合否は sum(log) で決める
+#
+# usage: ./check-regression.sh <基準のバイナリ> [n] [ratio] [-- 今のビルドへの引数...]
+#   BIN, SCORE で今のビルドと score の場所を変えられる
+
+set -ue
+
+cd "$(git rev-parse --show-toplevel)"
+
+base=$1
+shift
+n=50
+ratio=1.5
+if [ $# -gt 0 ] && [ "$1" != "--" ]; then
+  n=$1
+  shift
+fi
+if [ $# -gt 0 ] && [ "$1" != "--" ]; then
+  ratio=$1
+  shift
+fi
+if [ $# -gt 0 ]; then
+  shift
+fi
+bin=${BIN:-./target/release/ahc018}
+score=${SCORE:-./target/release/score}
+
+for s in $(seq -f '%04g' 0 $((n - 1))); do
+  a=$("$score" "$s" "$base" < "tools/in/$s.txt" 2> /dev/null | awk -v s="$s" '$1 == s {print $2}')
+  b=$("$score" "$s" "$bin" "$@" < "tools/in/$s.txt" 2> /dev/null | awk -v s="$s" '$1 == s {print $2}')
+  echo "$s ${a:-0} ${b:-0}"
+done | awk -v ratio="$ratio" '
+  $2 == 0 || $3 == 0 { print $1, "failed"; bad = 1; next }
+  {
+    sa += $2; sb += $3; la += log($2); lb += log($3)
+    if ($3 > $2 * ratio) printf "%s %d -> %d (x%.2f)\n", $1, $2, $3, $3 / $2
+  }
+  END {
+    printf "sum %d -> %d, sum(log) %.1f -> %.1f\n", sa, sb, la, lb
+    exit bad || lb > la
+  }
+'
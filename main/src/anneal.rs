@@ -0,0 +1,278 @@
+use crate::{
+    network::Network, shortest_path::DistanceField, steiner::prune, Grid, Point, SetMinMax,
+};
+use rand::{seq::SliceRandom, Rng};
+use std::time::Instant;
+
+// 締め切りを見る間隔
+const CHECK_INTERVAL: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct AnnealConfig {
+    pub iterations: usize,
+    // 温度はコストと同じ単位で、start から end へ指数的に下げる
+    pub start_temperature: f64,
+    pub end_temperature: f64,
+    // 近傍のうち、家のつなぎ先の水源を入れ替える割合。残りは道を 1 本外してつなぎ直す
+    pub swap_ratio: f64,
+}
+
+impl Default for AnnealConfig {
+    fn default() -> AnnealConfig {
+        AnnealConfig {
+            iterations: 1000,
+            start_temperature: 100.0,
+            end_temperature: 1.0,
+            swap_ratio: 0.3,
+        }
+    }
+}
+
+fn degree(cells: &Grid<bool>, p: Point) -> usize {
+    p.neighbors().filter(|&n| cells[n]).count()
+}
+
+pub fn total_cost(cost: &Grid<u32>, cells: &Grid<bool>) -> u64 {
+    cells
+        .iter()
+        .filter(|&(_, &b)| b)
+        .map(|(p, _)| cost[p] as u64)
+        .sum()
+}
+
+// network を空の状態 empty に戻してから、cells のマスを全部足す
+fn load(network: &mut Network, empty: (usize, usize), cells: &Grid<bool>) {
+    network.rollback(empty);
+    for (p, &b) in cells.iter() {
+        if b {
+            network.add(p);
+        }
+    }
+}
+
+// すべての家が水源につながっているか
+pub fn is_connected(water: &[Point], house: &[Point], cells: &Grid<bool>) -> bool {
+    let mut network = Network::new(water, house);
+    let empty = network.snapshot();
+    load(&mut network, empty, cells);
+    network.is_complete()
+}
+
+// 近傍を作るたびに最短路を引くので、距離のバッファと通るコストのグリッドを使い回す
+struct Annealer<'a> {
+    cost: &'a Grid<u32>,
+    max_cost: u32,
+    step: Grid<u32>,
+    field: DistanceField,
+}
+
+impl<'a> Annealer<'a> {
+    fn new(cost: &'a Grid<u32>) -> Annealer<'a> {
+        Annealer {
+            cost,
+            max_cost: cost.fold(0, |m, &c| m.max(c)),
+            step: Grid::new(0),
+            field: DistanceField::new(),
+        }
+    }
+
+    // sources から is_target なマスのどれかまでの最短路を足す。
+    // すでにネットワークにあるマスはただで通れ、blocked のマスは通らない
+    fn connect(
+        &mut self,
+        cells: &mut Grid<bool>,
+        sources: &[Point],
+        blocked: &[Point],
+        is_target: impl Fn(Point) -> bool,
+    ) -> bool {
+        self.step.zip_map(self.cost, |s, &c| *s = c);
+        self.step.zip_map(cells, |s, &b| {
+            if b {
+                *s = 0;
+            }
+        });
+        for &p in blocked {
+            self.step[p] = std::u32::MAX;
+        }
+        let sources = sources.iter().map(|&p| (p, 0));
+        match self
+            .field
+            .nearest(&self.step, self.max_cost, sources, is_target)
+        {
+            Some(t) => {
+                for p in self.field.path(t) {
+                    cells[p] = true;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // house から最初の端点の手前までの枝を外して、water へつなぎ直す
+    fn swap_source(
+        &mut self,
+        is_key: impl Fn(Point) -> bool,
+        cells: &mut Grid<bool>,
+        house: Point,
+        water: Point,
+    ) -> bool {
+        let mut cur = house;
+        loop {
+            let next = cur
+                .neighbors()
+                .find(|&n| cells[n] && n != house && !is_key(n));
+            match next {
+                Some(n) => {
+                    cells[n] = false;
+                    cur = n;
+                }
+                None => break,
+            }
+        }
+        self.connect(cells, &[house], &[], |p| p == water)
+    }
+}
+
+// 家・水源・分岐点・端以外のマスを 1 つ選び、それを含む端点同士の間の道を外す。外したマスを返す
+fn drop_segment<R: Rng>(
+    rng: &mut R,
+    is_key: impl Fn(Point) -> bool,
+    cells: &mut Grid<bool>,
+) -> Vec<Point> {
+    let inner = cells
+        .iter()
+        .filter(|&(p, &b)| b && !is_key(p))
+        .map(|(p, _)| p)
+        .collect::<Vec<_>>();
+    let start = match inner.choose(rng) {
+        Some(&p) => p,
+        None => return Vec::new(),
+    };
+    let mut segment = vec![start];
+    cells[start] = false;
+    let mut i = 0;
+    while i < segment.len() {
+        let p = segment[i];
+        i += 1;
+        for n in p.neighbors() {
+            if cells[n] && !is_key(n) {
+                cells[n] = false;
+                segment.push(n);
+            }
+        }
+    }
+    segment
+}
+
+// 掘るマスの集合 cells を、道のつなぎ替えを近傍にした焼きなましで安くする。
+// cells はすべての家が水源につながっていなければならない
+pub fn anneal<R: Rng>(
+    rng: &mut R,
+    cost: &Grid<u32>,
+    water: &[Point],
+    house: &[Point],
+    mut cells: Grid<bool>,
+    config: &AnnealConfig,
+    deadline: Option<Instant>,
+) -> Grid<bool> {
+    let mut fixed = Grid::new(false);
+    for &p in water.iter().chain(house.iter()) {
+        fixed[p] = true;
+    }
+    let mut is_water = Grid::new(false);
+    for &w in water {
+        is_water[w] = true;
+    }
+    prune(water, house, &mut cells);
+    let mut cur_cost = total_cost(cost, &cells);
+    let mut best = cells.clone();
+    let mut best_cost = cur_cost;
+    let mut annealer = Annealer::new(cost);
+    // 近傍ごとに Dsu を作り直さず、空の状態へ戻してからマスを足し直す
+    let mut network = Network::new(water, house);
+    let empty = network.snapshot();
+    for it in 0..config.iterations {
+        if it % CHECK_INTERVAL == 0 && matches!(deadline, Some(d) if d <= Instant::now()) {
+            break;
+        }
+        let t = it as f64 / config.iterations as f64;
+        let temperature = config.start_temperature.powf(1.0 - t) * config.end_temperature.powf(t);
+
+        let mut next = cells.clone();
+        let is_key = |p: Point| fixed[p] || degree(&cells, p) != 2;
+        let ok = if rng.gen::<f64>() < config.swap_ratio {
+            let h = *house.choose(rng).unwrap();
+            let w = *water.choose(rng).unwrap();
+            annealer.swap_source(is_key, &mut next, h, w)
+        } else {
+            let segment = drop_segment(rng, is_key, &mut next);
+            prune(water, house, &mut next);
+            load(&mut network, empty, &next);
+            let sources = next
+                .iter()
+                .filter(|&(p, &b)| b && !network.is_watered_cell(p))
+                .map(|(p, _)| p)
+                .collect::<Vec<_>>();
+            sources.is_empty()
+                || annealer.connect(&mut next, &sources, &segment, |p| {
+                    network.is_watered_cell(p) || is_water[p]
+                })
+        };
+        if !ok {
+            continue;
+        }
+        prune(water, house, &mut next);
+        load(&mut network, empty, &next);
+        if !network.is_complete() {
+            continue;
+        }
+        let next_cost = total_cost(cost, &next);
+        let delta = next_cost as f64 - cur_cost as f64;
+        if delta <= 0.0 || rng.gen::<f64>() < (-delta / temperature).exp() {
+            cells = next;
+            cur_cost = next_cost;
+            if best_cost.setmin(cur_cost) {
+                best = cells.clone();
+            }
+        }
+    }
+    best
+}
+
+#[test]
+fn test_anneal() {
+    use crate::{steiner::order_from_water, N};
+    use rand_pcg::Mcg128Xsl64;
+
+    // 遠回りの道から始めても、つながったまま安くなる
+    let mut rng = Mcg128Xsl64::new(1);
+    let mut cost = Grid::new(0);
+    for y in 0..N as u32 {
+        for x in 0..N as u32 {
+            cost[Point::new(x, y)] = rng.gen_range(1..100);
+        }
+    }
+    let water = vec![Point::new(10, 10), Point::new(150, 20)];
+    let house = vec![Point::new(100, 100), Point::new(30, 150)];
+    let mut cells = Grid::new(false);
+    for x in 10..=100 {
+        cells[Point::new(x, 10)] = true;
+    }
+    for y in 10..=150 {
+        cells[Point::new(100, y)] = true;
+    }
+    for x in 30..=100 {
+        cells[Point::new(x, 150)] = true;
+    }
+    let before = total_cost(&cost, &cells);
+    let config = AnnealConfig {
+        iterations: 100,
+        ..AnnealConfig::default()
+    };
+    let after = anneal(&mut rng, &cost, &water, &house, cells, &config, None);
+    assert!(is_connected(&water, &house, &after));
+    let (order, after_cost) = order_from_water(&cost, &water, &after);
+    assert!(house.iter().all(|h| order.contains(h)));
+    assert!(after_cost < before);
+}
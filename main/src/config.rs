@@ -0,0 +1,259 @@
+use crate::estimate::Method;
+#[cfg(feature = "cli")]
+use clap::Parser;
+use std::path::PathBuf;
+
+pub const DEFAULT_SEED: u64 = 1;
+pub const DEFAULT_TIME_LIMIT_MILLIS: u64 = 4000;
+pub const DEFAULT_SAMPLE_SHARE: f64 = 0.4;
+pub const DEFAULT_SAMPLES: usize = 10;
+pub const DEFAULT_MAX_ATTEMPTS: usize = 10000;
+pub const DEFAULT_THRESHOLD: f64 = 30.0;
+pub const DEFAULT_RESTARTS: usize = 10;
+pub const DEFAULT_ANNEAL: usize = 0;
+pub const DEFAULT_ANNEAL_TEMPERATURE: f64 = 100.0;
+pub const DEFAULT_ORDER: ConnectOrder = ConnectOrder::Random;
+pub const DEFAULT_REPLAN_INTERVAL: usize = 5;
+pub const DEFAULT_REPLAN_RESTARTS: usize = 3;
+pub const DEFAULT_MAX_REPLANS: usize = 20;
+pub const DEFAULT_MAX_REROUTES: usize = 20;
+pub const DEFAULT_SWITCH_MARGIN: f64 = 0.1;
+pub const DEFAULT_EXPLORE: bool = false;
+pub const DEFAULT_EXPLORE_CORRIDOR: u32 = 2;
+pub const DEFAULT_ESTIMATE: Option<Method> = Some(Method::Idw);
+pub const DEFAULT_WEIGHTING: Weighting = Weighting::Off;
+pub const DEFAULT_POOL: usize = 20;
+pub const DEFAULT_TEMPERATURE: f64 = 1.0;
+pub const DEFAULT_INFER: usize = 0;
+pub const DEFAULT_INFER_SEEDS: usize = 16;
+pub const DEFAULT_INFER_WEIGHT: f64 = 1.0;
+pub const DEFAULT_RISK: RiskMeasure = RiskMeasure::Mean;
+pub const DEFAULT_RISK_LEVEL: f64 = 0.8;
+pub const DEFAULT_RISK_PENALTY: f64 = 0.5;
+
+// 水源と家の配置の尤度をアンサンブルにどう反映するか
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Weighting {
+    /// 使わない
+    Off,
+    /// 各サンプルを尤度で重み付けする
+    Importance,
+    /// pool 個作ってから尤度に比例して選び直す
+    Resample,
+}
+
+// 経路探索で使うマスのコストを、アンサンブルから出したコストの分布のどの値にするか
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum RiskMeasure {
+    /// 期待値
+    Mean,
+    /// risk_level の分位点
+    Quantile,
+    /// risk_level より上側の平均
+    Cvar,
+    /// 期待値 + risk_penalty * 標準偏差
+    Variance,
+}
+
+// 家をどの順にネットワークへつなぐか
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ConnectOrder {
+    /// 順番を restarts 回シャッフルして一番安いもの
+    Random,
+    /// 家ごとに水源を決め、水源ごとのネットワークに一番近い家から。
+    /// 乱数を使わず 1 回で決まるので、restarts と replan_restarts は無視する
+    Prim,
+    /// 家ごとに水源を決め、2 番目に近い水源との差が大きい家から。prim と同じく 1 回で決まる
+    Regret,
+}
+
+// 提出用ビルドでは Default の値だけを使い、cli feature のときはコマンドライン引数で上書きできる
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "cli", derive(Parser))]
+pub struct SolverConfig {
+    /// 乱数の種
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_SEED))]
+    pub seed: u64,
+    /// 実行時間の制限 (ミリ秒)。解き直しと立て直しは、1 回あたりの平均の時間で残りに収まる分だけ行う
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_TIME_LIMIT_MILLIS))]
+    pub time_limit_millis: u64,
+    /// アンサンブルに使う高さマップの数
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_SAMPLES))]
+    pub samples: usize,
+    /// gen_h で landmark 付近の高さを受け入れるしきい値
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_THRESHOLD))]
+    pub threshold: f64,
+    /// 高さマップ作りに使ってよい、制限時間の割合
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_SAMPLE_SHARE))]
+    pub sample_share: f64,
+    /// 高さマップ 1 つあたりの棄却法の試行回数の上限。高さマップはこれと乱数の種だけで決まる
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_MAX_ATTEMPTS))]
+    pub max_attempts: usize,
+    /// 水源と家の配置の尤度の使い方
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_enum, default_value_t = DEFAULT_WEIGHTING)
+    )]
+    pub weighting: Weighting,
+    /// Resample のときに作る候補の数
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_POOL))]
+    pub pool: usize,
+    /// 尤度をこの値で割ってから重みにする
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_TEMPERATURE))]
+    pub temperature: f64,
+    /// 試し掘りの後、観測に合わせて推定した高さマップをこの数だけアンサンブルに加える。0 なら推定しない
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_INFER))]
+    pub infer: usize,
+    /// 推定で試す Perlin の種の数
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_INFER_SEEDS))]
+    pub infer_seeds: usize,
+    /// 推定した高さマップ 1 つあたりの重み
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_INFER_WEIGHT))]
+    pub infer_weight: f64,
+    /// 経路探索に使うマスのコストの見積もり方
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = DEFAULT_RISK))]
+    pub risk: RiskMeasure,
+    /// quantile と cvar で使う、分布の下からの割合。0 以上 1 以下で、外れた値は端に丸める
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_RISK_LEVEL))]
+    pub risk_level: f64,
+    /// variance で標準偏差に掛ける係数。0 以上で、0 なら mean と同じ
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_RISK_PENALTY))]
+    pub risk_penalty: f64,
+    /// 経路探索でマンハッタン距離を下界にした A* を使うか
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub astar: bool,
+    /// 家をつなぐ順の決め方
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = DEFAULT_ORDER))]
+    pub order: ConnectOrder,
+    /// 見積もりの硬さをこの割合より超えて壊れなかったマスは、迂回した方が安ければ諦める。None なら諦めない
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub abandon_margin: Option<f64>,
+    /// 諦めるか考える回数の上限。1 回ごとに立て直しと同じだけ計画を作り直す
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_MAX_REROUTES))]
+    pub max_reroutes: usize,
+    /// 最初の計画を立てるときの再始動回数。order が random のときだけ使う
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_RESTARTS))]
+    pub restarts: usize,
+    /// 最初の計画を焼きなましで改善する反復回数。0 なら焼きなまさない
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_ANNEAL))]
+    pub anneal: usize,
+    /// 焼きなましの初期温度 (コストと同じ単位)
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_ANNEAL_TEMPERATURE))]
+    pub anneal_temperature: f64,
+    /// 壊したマスがこの数たまるごとに掘る順を立て直す。0 なら最初の計画のまま掘る
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_REPLAN_INTERVAL))]
+    pub replan_interval: usize,
+    /// 立て直す回数の上限。最初の計画がこの回数で行き渡るよう、replan_interval より間隔を広げることがある
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_MAX_REPLANS))]
+    pub max_replans: usize,
+    /// 立て直すときの再始動回数 (1 以上)。order が random のときだけ使う
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_REPLAN_RESTARTS))]
+    pub replan_restarts: usize,
+    /// 見積もりの誤差で計画を行ったり来たりしないよう、これだけ安くならないと乗り換えない
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_SWITCH_MARGIN))]
+    pub switch_margin: f64,
+    /// 道を決める前に格子状に試し掘りするか
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, default_value_t = DEFAULT_EXPLORE, action = clap::ArgAction::Set)
+    )]
+    pub explore: bool,
+    /// 試し掘りの格子の間隔 (1 以上)。指定しなければ c に合わせて 10, 14, 20 のどれか
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub explore_stride: Option<u32>,
+    /// 試し掘りで 1 マスを壊すまでに順に使うパワー (カンマ区切り)。使い切ったら最後の値を繰り返す。
+    /// 指定しなければ max(2c, 20) から倍々に 8 段
+    #[cfg_attr(feature = "cli", arg(long, value_delimiter = ','))]
+    pub explore_powers: Option<Vec<u32>>,
+    /// 家と水源を結ぶ線分から、格子の間隔のこの倍の距離以内だけ試し掘りする。0 なら盤面全体
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_EXPLORE_CORRIDOR))]
+    pub explore_corridor: u32,
+    /// 観測した硬さをどう補間して見積もりに使うか (none, idw, kriging)。
+    // clap の derive は Option<T> を省略可能な T の引数として扱うので、完全修飾名で避けている
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, default_value = "idw", value_parser = parse_estimate)
+    )]
+    pub estimate: ::std::option::Option<Method>,
+    /// 候補が尽きたときに使う固定のパワー。None なら事前分布の表を使う
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub fallback_power: Option<u32>,
+    /// 高さマップ作りと再始動に使うスレッド数。None なら parallel feature のときは使えるだけ、そうでなければ 1
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub threads: Option<usize>,
+    /// ジャッジとのやり取りを書き出すファイル
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub record: Option<PathBuf>,
+    /// record で書き出したファイルから応答を返し、ジャッジなしで同じ実行をなぞる。
+    /// 入力は普段どおり標準入力から読む。時間で回数が減るとずれるので、
+    /// 記録と再生のどちらも time_limit_millis を大きくしておく
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub replay: Option<PathBuf>,
+}
+
+impl Default for SolverConfig {
+    fn default() -> SolverConfig {
+        SolverConfig {
+            seed: DEFAULT_SEED,
+            time_limit_millis: DEFAULT_TIME_LIMIT_MILLIS,
+            samples: DEFAULT_SAMPLES,
+            threshold: DEFAULT_THRESHOLD,
+            sample_share: DEFAULT_SAMPLE_SHARE,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            weighting: DEFAULT_WEIGHTING,
+            pool: DEFAULT_POOL,
+            temperature: DEFAULT_TEMPERATURE,
+            infer: DEFAULT_INFER,
+            infer_seeds: DEFAULT_INFER_SEEDS,
+            infer_weight: DEFAULT_INFER_WEIGHT,
+            risk: DEFAULT_RISK,
+            risk_level: DEFAULT_RISK_LEVEL,
+            risk_penalty: DEFAULT_RISK_PENALTY,
+            astar: false,
+            order: DEFAULT_ORDER,
+            abandon_margin: None,
+            max_reroutes: DEFAULT_MAX_REROUTES,
+            restarts: DEFAULT_RESTARTS,
+            anneal: DEFAULT_ANNEAL,
+            anneal_temperature: DEFAULT_ANNEAL_TEMPERATURE,
+            replan_interval: DEFAULT_REPLAN_INTERVAL,
+            replan_restarts: DEFAULT_REPLAN_RESTARTS,
+            max_replans: DEFAULT_MAX_REPLANS,
+            switch_margin: DEFAULT_SWITCH_MARGIN,
+            explore: DEFAULT_EXPLORE,
+            explore_stride: None,
+            explore_powers: None,
+            explore_corridor: DEFAULT_EXPLORE_CORRIDOR,
+            estimate: DEFAULT_ESTIMATE,
+            fallback_power: None,
+            threads: None,
+            record: None,
+            replay: None,
+        }
+    }
+}
+
+impl SolverConfig {
+    #[cfg(feature = "cli")]
+    pub fn from_args() -> SolverConfig {
+        SolverConfig::parse()
+    }
+
+    #[cfg(not(feature = "cli"))]
+    pub fn from_args() -> SolverConfig {
+        SolverConfig::default()
+    }
+}
+
+#[cfg(feature = "cli")]
+fn parse_estimate(s: &str) -> Result<Option<Method>, String> {
+    match s {
+        "none" => Ok(None),
+        "idw" => Ok(Some(Method::Idw)),
+        "kriging" => Ok(Some(Method::Kriging)),
+        _ => Err(format!("unknown method: {}", s)),
+    }
+}
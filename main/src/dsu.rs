@@ -40,3 +40,109 @@ impl Dsu {
         self.leader(a) == self.leader(b)
     }
 }
+
+// 経路圧縮をしない代わりに、merge や印付けを snapshot の時点まで戻せる Dsu。
+// 成分ごとに大きさと水源を含むかを持つ
+#[derive(Clone)]
+pub struct RollbackDsu {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+    water: Vec<bool>,
+    // 変更前の (代表元, 親, 大きさ, 水源)
+    history: Vec<(usize, usize, usize, bool)>,
+}
+
+impl RollbackDsu {
+    pub fn new(size: usize) -> Self {
+        Self {
+            parent: (0..size).collect(),
+            size: vec![1; size],
+            water: vec![false; size],
+            history: Vec::new(),
+        }
+    }
+
+    fn save(&mut self, x: usize) {
+        self.history
+            .push((x, self.parent[x], self.size[x], self.water[x]));
+    }
+
+    pub fn merge(&mut self, a: usize, b: usize) -> bool {
+        let (mut x, mut y) = (self.leader(a), self.leader(b));
+        if x == y {
+            return false;
+        }
+        if self.size[x] < self.size[y] {
+            std::mem::swap(&mut x, &mut y);
+        }
+        self.save(x);
+        self.save(y);
+        self.parent[y] = x;
+        self.size[x] += self.size[y];
+        self.water[x] |= self.water[y];
+        true
+    }
+
+    pub fn leader(&self, a: usize) -> usize {
+        let mut x = a;
+        while self.parent[x] != x {
+            x = self.parent[x];
+        }
+        x
+    }
+
+    pub fn same(&self, a: usize, b: usize) -> bool {
+        self.leader(a) == self.leader(b)
+    }
+
+    pub fn size(&self, a: usize) -> usize {
+        self.size[self.leader(a)]
+    }
+
+    pub fn has_water(&self, a: usize) -> bool {
+        self.water[self.leader(a)]
+    }
+
+    // a は水源
+    pub fn mark_water(&mut self, a: usize) {
+        let x = self.leader(a);
+        self.save(x);
+        self.water[x] = true;
+    }
+
+    pub fn snapshot(&self) -> usize {
+        self.history.len()
+    }
+
+    // snapshot を取った後の merge と印付けを新しい順に取り消す
+    pub fn rollback(&mut self, snapshot: usize) {
+        while snapshot < self.history.len() {
+            let (x, parent, size, water) = self.history.pop().unwrap();
+            self.parent[x] = parent;
+            self.size[x] = size;
+            self.water[x] = water;
+        }
+    }
+}
+
+#[test]
+fn test_rollback_dsu() {
+    let mut dsu = RollbackDsu::new(6);
+    dsu.mark_water(0);
+    dsu.merge(0, 1);
+    let snapshot = dsu.snapshot();
+    dsu.merge(2, 3);
+    dsu.merge(1, 3);
+    dsu.merge(4, 2);
+    assert!(dsu.same(0, 4));
+    assert_eq!(dsu.size(3), 5);
+    assert!(dsu.has_water(4));
+
+    dsu.rollback(snapshot);
+    assert!(dsu.same(0, 1));
+    assert!(!dsu.same(1, 3));
+    assert!(!dsu.same(2, 3));
+    assert_eq!(dsu.size(0), 2);
+    assert!(dsu.has_water(1));
+    assert!(!dsu.has_water(3));
+}
@@ -0,0 +1,273 @@
+use crate::{Grid, Point};
+use rustc_hash::FxHashMap;
+use std::cmp::Ordering;
+
+// 観測の影響が 1/e になる距離と、打ち切る距離
+const OBS_DECAY: f64 = 8.0;
+pub const OBS_RADIUS: u32 = 32;
+// 補正量を決めるときの事前サンプル自身の重み
+const PRIOR_K: f64 = 0.1;
+// 補正前のサンプルを混ぜるときの重み
+const RAW_W: f64 = 0.5;
+// 硬さの周辺分布を作るときに間引く間隔
+const PRIOR_STRIDE: usize = 4;
+const MAX_H: u32 = 5000;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct Interval {
+    // lower < h <= upper
+    pub lower: u32,
+    pub upper: Option<u32>,
+}
+
+impl Interval {
+    pub fn intersect(&self, other: &Interval) -> Interval {
+        let upper = match (self.upper, other.upper) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        Interval {
+            lower: self.lower.max(other.lower),
+            upper,
+        }
+    }
+}
+
+pub struct Ensemble {
+    h: Vec<Grid<u32>>,
+    // 各サンプルの重み。平均が 1
+    weight: Vec<f64>,
+    observed: FxHashMap<Point, Interval>,
+    // 観測したマスごとの、各サンプルをその観測に合わせる補正量
+    residual: FxHashMap<Point, Vec<f64>>,
+    // 全サンプルの全マスの硬さを間引いて昇順に並べたもの
+    prior: Vec<u32>,
+}
+
+impl Ensemble {
+    pub fn new(h: Vec<Grid<u32>>) -> Ensemble {
+        let weight = vec![1.0; h.len()];
+        Ensemble::with_weights(h, weight)
+    }
+
+    pub fn with_weights(h: Vec<Grid<u32>>, weight: Vec<f64>) -> Ensemble {
+        let mean = weight.iter().sum::<f64>() / weight.len() as f64;
+        let mut ensemble = Ensemble {
+            h,
+            weight: weight.into_iter().map(|w| w / mean).collect(),
+            observed: FxHashMap::default(),
+            residual: FxHashMap::default(),
+            prior: Vec::new(),
+        };
+        ensemble.update_prior();
+        ensemble
+    }
+
+    pub fn push(&mut self, h: Grid<u32>, weight: f64) {
+        self.h.push(h);
+        self.weight.push(weight);
+        self.update_prior();
+        let observed = self.observations();
+        for (o, interval) in observed {
+            let r = self.residuals(o, &interval);
+            self.residual.insert(o, r);
+        }
+    }
+
+    fn update_prior(&mut self) {
+        self.prior = self
+            .h
+            .iter()
+            .flat_map(|h| {
+                h.iter()
+                    .filter(|(p, _)| {
+                        p.x() as usize % PRIOR_STRIDE == 0 && p.y() as usize % PRIOR_STRIDE == 0
+                    })
+                    .map(|(_, &v)| v)
+            })
+            .collect();
+        self.prior.sort();
+    }
+
+    // 硬さが lower より大きいという条件の下での、周辺分布の q 分位点。
+    // lower より硬い値がなければ (lower, MAX_H] の一様分布で代える
+    fn conditional_quantile(&self, lower: u32, q: f64) -> f64 {
+        // lower 以下と lower より大きい値の境目。Equal を返さないので見つからず、境目の位置が返る
+        let start = self
+            .prior
+            .binary_search_by(|&v| {
+                if v <= lower {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            })
+            .unwrap_err();
+        let rest = self.prior.len() - start;
+        if rest == 0 {
+            return lower as f64 + q * (MAX_H.max(lower) - lower) as f64;
+        }
+        let k = ((q * rest as f64) as usize).min(rest - 1);
+        self.prior[start + k] as f64
+    }
+
+    // values を観測 interval に合わせる。壊れたときは区間の中点にする。壊れていないときは
+    // lower 以下の値だけを lower より上の条件付き分位点へ移す。値同士の順位は保つので、lower のすぐ上に固まらない
+    fn condition(&self, interval: &Interval, values: &mut [f64]) {
+        let lower = interval.lower;
+        if let Some(upper) = interval.upper {
+            let mid = (lower + upper) as f64 / 2.0;
+            for v in values.iter_mut() {
+                *v = mid;
+            }
+            return;
+        }
+        let mut below = (0..values.len())
+            .filter(|&i| values[i] <= lower as f64)
+            .collect::<Vec<_>>();
+        below.sort_by(|&a, &b| values[a].partial_cmp(&values[b]).unwrap().then(a.cmp(&b)));
+        let n = below.len();
+        for (rank, i) in below.into_iter().enumerate() {
+            values[i] = self.conditional_quantile(lower, (rank as f64 + 0.5) / n as f64);
+        }
+    }
+
+    // o の観測 interval に合わせるための、各サンプルの補正量
+    fn residuals(&self, o: Point, interval: &Interval) -> Vec<f64> {
+        let raw = self.h.iter().map(|h| h[o] as f64).collect::<Vec<_>>();
+        let mut values = raw.clone();
+        self.condition(interval, &mut values);
+        values.iter().zip(raw).map(|(v, r)| v - r).collect()
+    }
+
+    pub fn samples(&self) -> &[Grid<u32>] {
+        &self.h
+    }
+
+    pub fn observed(&self, p: Point) -> Option<Interval> {
+        self.observed.get(&p).copied()
+    }
+
+    pub fn observations(&self) -> Vec<(Point, Interval)> {
+        self.observed.iter().map(|(&p, &i)| (p, i)).collect()
+    }
+
+    // 各サンプルを近くの観測に合わせて補正した p の値。
+    // p 自身の観測は補正量を混ぜるのではなく、補正した後の値を条件付けて必ず満たす
+    pub fn values_at(&self, p: Point) -> Vec<u32> {
+        let mut sum_k = PRIOR_K;
+        let mut shift = vec![0.0; self.h.len()];
+        for (&o, residual) in self.residual.iter() {
+            let d = p.manhattan(&o);
+            if d > OBS_RADIUS || o == p {
+                continue;
+            }
+            let k = (-(d as f64) / OBS_DECAY).exp();
+            sum_k += k;
+            for (shift, r) in shift.iter_mut().zip(residual.iter()) {
+                *shift += k * r;
+            }
+        }
+        let mut values = self
+            .h
+            .iter()
+            .zip(shift)
+            .map(|(h, shift)| h[p] as f64 + shift / sum_k)
+            .collect::<Vec<_>>();
+        if let Some(interval) = self.observed.get(&p) {
+            self.condition(interval, &mut values);
+        }
+        values
+            .into_iter()
+            .map(|v| v.max(10.0).min(5000.0).round() as u32)
+            .collect()
+    }
+
+    // 累積パワー s までで壊れなかった / s + power で壊れた、を観測する
+    pub fn observe(&mut self, p: Point, s: u32, power: u32, broke: bool) {
+        let obs = if broke {
+            Interval {
+                lower: s,
+                upper: Some(s + power),
+            }
+        } else {
+            Interval {
+                lower: s + power,
+                upper: None,
+            }
+        };
+        let new = self
+            .observed
+            .get(&p)
+            .copied()
+            .unwrap_or_default()
+            .intersect(&obs);
+        self.observed.insert(p, new);
+        let r = self.residuals(p, &new);
+        self.residual.insert(p, r);
+    }
+
+    // p の観測で条件付けた、p の硬さの重み付き平均。観測していなければサンプルのままの平均
+    pub fn conditional_mean(&self, p: Point) -> f64 {
+        let residual = self.residual.get(&p);
+        let sum = self
+            .h
+            .iter()
+            .zip(self.weight.iter())
+            .enumerate()
+            .map(|(i, (h, &w))| (h[p] as f64 + residual.map_or(0.0, |r| r[i])) * w)
+            .sum::<f64>();
+        sum / self.weight.iter().sum::<f64>()
+    }
+
+    // 補正だけだとばらつきが潰れるので補正前のサンプルも混ぜる
+    pub fn weighted_values_at(&self, p: Point) -> Vec<(u32, f64)> {
+        let raw = self
+            .h
+            .iter()
+            .zip(self.weight.iter())
+            .map(|(h, &w)| (h[p], RAW_W * w));
+        self.values_at(p)
+            .into_iter()
+            .zip(self.weight.iter())
+            .map(|(h, &w)| (h, w))
+            .chain(raw)
+            .collect()
+    }
+}
+
+#[test]
+fn test_not_break_keeps_spread() {
+    use crate::power::best_power;
+
+    // p の硬さだけ違う 10 枚のサンプル
+    let p = Point::new(5, 5);
+    let ensemble = |f: fn(u32) -> u32| {
+        let h = (0..10)
+            .map(|i| {
+                let mut h = Grid::new(100 + 10 * i);
+                h[p] = f(i);
+                h
+            })
+            .collect::<Vec<_>>();
+        Ensemble::new(h)
+    };
+
+    // どのサンプルよりも硬かったマスでも、次の一打が 1 のような小さな値にならない
+    let mut all_below = ensemble(|i| 50 + 10 * i);
+    all_below.observe(p, 0, 300, false);
+    let v = all_below.weighted_values_at(p);
+    assert_eq!(v.iter().filter(|&&(h, _)| 300 < h).count(), 10);
+    let (power, _) = best_power(&v, 1, 300).unwrap();
+    assert!(100 <= power, "{}", power);
+
+    // 下限を超えていたサンプルはそのままで、超えていなかったものも下限のすぐ上には固まらない
+    let mut some_below = ensemble(|i| 50 + 100 * i);
+    some_below.observe(p, 0, 300, false);
+    let values = some_below.values_at(p);
+    for (i, &v) in values.iter().enumerate().skip(3) {
+        assert_eq!(v, 50 + 100 * i as u32);
+    }
+    assert!(values.iter().all(|&v| 350 <= v), "{:?}", values);
+    assert!(350.0 <= some_below.conditional_mean(p));
+}
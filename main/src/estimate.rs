@@ -0,0 +1,355 @@
+use crate::{ensemble::Interval, Point};
+
+// 観測が少なすぎるときや遠いときに使う全体のばらつき
+const DEFAULT_SD: f64 = 1500.0;
+const IDW_POWER: f64 = 2.0;
+// IDW で最寄りの観測からこの距離離れると全体のばらつきにほぼ戻る
+const IDW_RANGE: f64 = 20.0;
+// 経験バリオグラムのビン幅と最大距離
+const BIN_WIDTH: f64 = 4.0;
+const MAX_LAG: f64 = 60.0;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Method {
+    Idw,
+    Kriging,
+}
+
+// 指数型: gamma(h) = nugget + psill * (1 - exp(-h / range))
+#[derive(Debug, Copy, Clone)]
+pub struct Variogram {
+    pub nugget: f64,
+    pub psill: f64,
+    pub range: f64,
+}
+
+impl Variogram {
+    fn covariance(&self, h: f64) -> f64 {
+        if h == 0.0 {
+            self.nugget + self.psill
+        } else {
+            self.psill * (-h / self.range).exp()
+        }
+    }
+
+    // 経験バリオグラムに range を総当たりし、nugget と psill は重み付き最小二乗で決める
+    pub fn fit(obs: &[Sample]) -> Variogram {
+        let bins = (MAX_LAG / BIN_WIDTH) as usize;
+        let mut sum = vec![0.0; bins];
+        let mut cnt = vec![0.0; bins];
+        for (i, a) in obs.iter().enumerate() {
+            for b in &obs[i + 1..] {
+                let h = a.distance(b);
+                let k = (h / BIN_WIDTH) as usize;
+                if k < bins {
+                    sum[k] += (a.value - b.value).powi(2) / 2.0;
+                    cnt[k] += 1.0;
+                }
+            }
+        }
+        let var = variance(obs).max(1.0);
+        let mut best = Variogram {
+            nugget: 0.0,
+            psill: var,
+            range: IDW_RANGE,
+        };
+        let mut best_err = std::f64::MAX;
+        for range in (2..=40).map(|r| r as f64 * 2.5) {
+            // gamma = nugget + psill * f の 2 変数の最小二乗
+            let (mut sw, mut sf, mut sff, mut sg, mut sfg) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for k in 0..bins {
+                if cnt[k] == 0.0 {
+                    continue;
+                }
+                let h = (k as f64 + 0.5) * BIN_WIDTH;
+                let f = 1.0 - (-h / range).exp();
+                let g = sum[k] / cnt[k];
+                let w = cnt[k];
+                sw += w;
+                sf += w * f;
+                sff += w * f * f;
+                sg += w * g;
+                sfg += w * f * g;
+            }
+            let det = sw * sff - sf * sf;
+            if det.abs() < 1e-9 {
+                continue;
+            }
+            let psill = ((sw * sfg - sf * sg) / det).max(1.0);
+            let nugget = ((sg - psill * sf) / sw).max(0.0);
+            let v = Variogram {
+                nugget,
+                psill,
+                range,
+            };
+            let mut err = 0.0;
+            for k in 0..bins {
+                if cnt[k] == 0.0 {
+                    continue;
+                }
+                let h = (k as f64 + 0.5) * BIN_WIDTH;
+                let g = v.nugget + v.psill * (1.0 - (-h / v.range).exp());
+                err += cnt[k] * (sum[k] / cnt[k] - g).powi(2);
+            }
+            if err < best_err {
+                best_err = err;
+                best = v;
+            }
+        }
+        best
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Sample {
+    pub p: Point,
+    pub value: f64,
+    // 観測区間の幅からくる誤差の分散
+    pub error: f64,
+}
+
+impl Sample {
+    // mean は区間で条件付けた硬さの平均で、これを値とする。まだ壊れていないときに下限を値にすると、
+    // 本当の硬さより必ず低くなって推定が下に偏る。上に開いた区間の誤差は、下限から mean までを標準偏差とみる
+    pub fn from_interval(p: Point, interval: Interval, mean: f64) -> Sample {
+        let error = match interval.upper {
+            Some(upper) => ((upper - interval.lower) as f64).powi(2) / 12.0,
+            None => (mean - interval.lower as f64).max(1.0).powi(2),
+        };
+        Sample {
+            p,
+            value: mean,
+            error,
+        }
+    }
+
+    fn distance(&self, other: &Sample) -> f64 {
+        distance(self.p, other.p)
+    }
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    let dx = a.x() as f64 - b.x() as f64;
+    let dy = a.y() as f64 - b.y() as f64;
+    (dx * dx + dy * dy).sqrt()
+}
+
+fn mean(obs: &[Sample]) -> f64 {
+    obs.iter().map(|o| o.value).sum::<f64>() / obs.len() as f64
+}
+
+fn variance(obs: &[Sample]) -> f64 {
+    let m = mean(obs);
+    obs.iter().map(|o| (o.value - m).powi(2)).sum::<f64>() / obs.len() as f64
+}
+
+// 疎な観測から各マスの硬さの期待値と標準偏差を推定する。
+// 各マスにはマンハッタン距離 radius 以内の観測だけを使うので、観測を足したときに推定が変わるのはその周りだけになる。
+// 全体の平均とバリオグラムは new のときに決めたまま変えない
+pub struct Estimator {
+    method: Method,
+    obs: Vec<Sample>,
+    neighbors: usize,
+    radius: u32,
+    // 当てはめに使った観測の数
+    fitted: usize,
+    mean: f64,
+    sd: f64,
+    variogram: Variogram,
+}
+
+impl Estimator {
+    pub fn new(method: Method, obs: Vec<Sample>, neighbors: usize, radius: u32) -> Estimator {
+        let (mean, sd) = if obs.len() < 2 {
+            (obs.first().map_or(0.0, |o| o.value), DEFAULT_SD)
+        } else {
+            (mean(&obs), variance(&obs).sqrt())
+        };
+        let variogram = if obs.len() < 3 {
+            Variogram {
+                nugget: 0.0,
+                psill: sd * sd,
+                range: IDW_RANGE,
+            }
+        } else {
+            Variogram::fit(&obs)
+        };
+        Estimator {
+            method,
+            fitted: obs.len(),
+            obs,
+            neighbors,
+            radius,
+            mean,
+            sd,
+            variogram,
+        }
+    }
+
+    // 当てはめ直さずに観測だけを入れ替える
+    pub fn set_observations(&mut self, obs: Vec<Sample>) {
+        self.obs = obs;
+    }
+
+    pub fn fitted(&self) -> usize {
+        self.fitted
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.obs.is_empty()
+    }
+
+    pub fn variogram(&self) -> Variogram {
+        self.variogram
+    }
+
+    // p の硬さの (期待値, 標準偏差)。radius 以内に観測がなければ全体の平均
+    pub fn estimate_at(&self, p: Point) -> (f64, f64) {
+        let near = self.nearest(p);
+        if near.is_empty() {
+            return (self.mean, DEFAULT_SD);
+        }
+        match self.method {
+            Method::Idw => self.idw(&near),
+            Method::Kriging => self.kriging(p, &near).unwrap_or_else(|| self.idw(&near)),
+        }
+    }
+
+    fn nearest(&self, p: Point) -> Vec<(f64, &Sample)> {
+        let mut near = self
+            .obs
+            .iter()
+            .filter(|o| p.manhattan(&o.p) <= self.radius)
+            .map(|o| (distance(p, o.p), o))
+            .collect::<Vec<_>>();
+        near.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        near.truncate(self.neighbors);
+        near
+    }
+
+    fn idw(&self, near: &[(f64, &Sample)]) -> (f64, f64) {
+        if near[0].0 == 0.0 {
+            return (near[0].1.value, near[0].1.error.sqrt());
+        }
+        let mut sw = 0.0;
+        let mut sv = 0.0;
+        for &(d, o) in near {
+            let w = 1.0 / d.powf(IDW_POWER);
+            sw += w;
+            sv += w * o.value;
+        }
+        let m = sv / sw;
+        let spread = near
+            .iter()
+            .map(|&(d, o)| (o.value - m).powi(2) / d.powf(IDW_POWER))
+            .sum::<f64>()
+            / sw;
+        let dmin = near[0].0;
+        let far = self.sd * self.sd * (1.0 - (-dmin / IDW_RANGE).exp());
+        (m, (spread + far).sqrt())
+    }
+
+    // 近傍の観測だけを使う局所的な通常クリギング
+    fn kriging(&self, p: Point, near: &[(f64, &Sample)]) -> Option<(f64, f64)> {
+        let n = near.len();
+        let v = &self.variogram;
+        // [C 1; 1 0] [lambda; mu] = [c0; 1]
+        let mut a = vec![vec![0.0; n + 2]; n + 1];
+        for i in 0..n {
+            for j in 0..n {
+                a[i][j] = v.covariance(near[i].1.distance(near[j].1));
+            }
+            a[i][i] += near[i].1.error;
+            a[i][n] = 1.0;
+            a[n][i] = 1.0;
+            a[i][n + 1] = v.covariance(distance(p, near[i].1.p));
+        }
+        a[n][n + 1] = 1.0;
+        let x = solve(a)?;
+        let m = (0..n).map(|i| x[i] * near[i].1.value).sum::<f64>();
+        let var = v.covariance(0.0)
+            - (0..n)
+                .map(|i| x[i] * v.covariance(distance(p, near[i].1.p)))
+                .sum::<f64>()
+            - x[n];
+        Some((m, var.max(0.0).sqrt()))
+    }
+}
+
+// 拡大係数行列 a を部分ピボット付きのガウスの消去法で解く
+fn solve(mut a: Vec<Vec<f64>>) -> Option<Vec<f64>> {
+    let n = a.len();
+    for i in 0..n {
+        let pivot = (i..n).max_by(|&x, &y| a[x][i].abs().partial_cmp(&a[y][i].abs()).unwrap())?;
+        if a[pivot][i].abs() < 1e-9 {
+            return None;
+        }
+        a.swap(i, pivot);
+        let row = a[i].clone();
+        for (j, r) in a.iter_mut().enumerate() {
+            if j != i {
+                let f = r[i] / row[i];
+                if f != 0.0 {
+                    for (x, &y) in r[i..].iter_mut().zip(&row[i..]) {
+                        *x -= f * y;
+                    }
+                }
+            }
+        }
+    }
+    Some((0..n).map(|i| a[i][n] / a[i][i]).collect())
+}
+
+#[test]
+fn test_solve() {
+    // 2x + y - z = 8, -3x - y + 2z = -11, -2x + y + 2z = -3 の解は (2, 3, -1)
+    let a = vec![
+        vec![2.0, 1.0, -1.0, 8.0],
+        vec![-3.0, -1.0, 2.0, -11.0],
+        vec![-2.0, 1.0, 2.0, -3.0],
+    ];
+    let x = solve(a).unwrap();
+    for (x, e) in x.iter().zip([2.0, 3.0, -1.0].iter()) {
+        assert!((x - e).abs() < 1e-9);
+    }
+    assert!(solve(vec![vec![1.0, 2.0, 3.0], vec![2.0, 4.0, 6.0]]).is_none());
+}
+
+#[test]
+fn test_kriging_reproduces_observations() {
+    // 幅の狭い観測の点では、観測した値をほぼそのまま返す
+    let observations = (0..20)
+        .map(|i| {
+            let p = Point::new(10 * (i % 5) + 3, 12 * (i / 5) + 5);
+            let h = 500 + 97 * ((i * 7) % 20);
+            let interval = Interval {
+                lower: h - 1,
+                upper: Some(h + 1),
+            };
+            (p, interval)
+        })
+        .collect::<Vec<_>>();
+    let samples = observations
+        .iter()
+        .map(|&(p, i)| Sample::from_interval(p, i, (i.lower + 1) as f64))
+        .collect();
+    let estimator = Estimator::new(Method::Kriging, samples, 12, 32);
+    for &(p, interval) in observations.iter() {
+        let (mean, sd) = estimator.estimate_at(p);
+        assert!(
+            (mean - (interval.lower + 1) as f64).abs() < 1.0,
+            "{:?} {}",
+            p,
+            mean
+        );
+        assert!(sd < 5.0);
+    }
+    // 観測から radius より遠いマスは全体の平均になる
+    let (mean, _) = estimator.estimate_at(Point::new(190, 190));
+    let expected = observations
+        .iter()
+        .map(|&(_, i)| (i.lower + 1) as f64)
+        .sum::<f64>()
+        / observations.len() as f64;
+    assert!((mean - expected).abs() < 1e-6);
+}
@@ -0,0 +1,141 @@
+use crate::{
+    config::{SolverConfig, DEFAULT_EXPLORE_CORRIDOR},
+    judge::{DigResult, Judge, JudgeError},
+    solver::Solver,
+    Point, N,
+};
+
+const MAX_POWER: u32 = 5000;
+
+#[derive(Debug, Clone)]
+pub struct ExploreConfig {
+    // 格子の間隔
+    pub stride: u32,
+    // 1 マスを壊すまでに順に使うパワー。使い切ったら最後の値を繰り返す
+    pub powers: Vec<u32>,
+    // 家と水源を結ぶ線分からこの距離以内だけ調べる。None なら盤面全体
+    pub corridor: Option<u32>,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ExploreReport {
+    pub probes: usize,
+    pub strikes: usize,
+    pub cost: u64,
+    // 調べている間に全部つながった
+    pub finished: bool,
+}
+
+impl ExploreConfig {
+    // c が大きいほど 1 回掘る手間が重いので、間隔を広げて強めに掘る
+    pub fn for_c(c: u32) -> ExploreConfig {
+        let stride = match c {
+            0..=4 => 10,
+            5..=32 => 14,
+            _ => 20,
+        };
+        let base = (2 * c).max(20);
+        let powers = (0..8).map(|i| (base << i).min(MAX_POWER)).collect();
+        ExploreConfig {
+            stride,
+            powers,
+            corridor: Some(stride * DEFAULT_EXPLORE_CORRIDOR),
+        }
+    }
+
+    // for_c の値のうち、config で指定されたものを置き換える
+    pub fn new(c: u32, config: &SolverConfig) -> ExploreConfig {
+        let default = ExploreConfig::for_c(c);
+        let stride = config.explore_stride.unwrap_or(default.stride);
+        let corridor = match config.explore_corridor {
+            0 => None,
+            k => Some(stride * k),
+        };
+        ExploreConfig {
+            stride,
+            powers: config.explore_powers.clone().unwrap_or(default.powers),
+            corridor,
+        }
+    }
+
+    pub fn power(&self, strike: usize) -> u32 {
+        self.powers[strike.min(self.powers.len() - 1)]
+    }
+
+    pub fn lattice(&self, water: &[Point], house: &[Point]) -> Vec<Point> {
+        let offset = self.stride / 2;
+        let mut cells = Vec::new();
+        for x in (offset..N as u32).step_by(self.stride as usize) {
+            for y in (offset..N as u32).step_by(self.stride as usize) {
+                let p = Point::new(x, y);
+                if let Some(width) = self.corridor {
+                    if !in_corridor(p, water, house, width) {
+                        continue;
+                    }
+                }
+                cells.push(p);
+            }
+        }
+        cells
+    }
+}
+
+fn segment_distance(p: Point, a: Point, b: Point) -> f64 {
+    let (px, py) = (p.x() as f64, p.y() as f64);
+    let (ax, ay) = (a.x() as f64, a.y() as f64);
+    let (bx, by) = (b.x() as f64, b.y() as f64);
+    let (dx, dy) = (bx - ax, by - ay);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 == 0.0 {
+        0.0
+    } else {
+        (((px - ax) * dx + (py - ay) * dy) / len2).max(0.0).min(1.0)
+    };
+    let (qx, qy) = (ax + t * dx, ay + t * dy);
+    ((px - qx).powi(2) + (py - qy).powi(2)).sqrt()
+}
+
+// 各家から一番近い水源への線分の近くにあるか
+fn in_corridor(p: Point, water: &[Point], house: &[Point], width: u32) -> bool {
+    house.iter().any(|&h| {
+        let w = *water.iter().min_by_key(|w| w.manhattan(&h)).unwrap();
+        segment_distance(p, h, w) <= width as f64
+    })
+}
+
+// 道を決める前に格子状にマスを掘って硬さを測り、solver に観測として渡す
+pub fn explore<J: Judge>(
+    judge: &mut J,
+    solver: &mut Solver,
+    config: &ExploreConfig,
+) -> Result<ExploreReport, JudgeError> {
+    let mut report = ExploreReport::default();
+    for p in config.lattice(solver.water(), solver.house()) {
+        if solver.is_broken(p) {
+            continue;
+        }
+        report.probes += 1;
+        let mut s = 0;
+        for strike in 0.. {
+            let power = config.power(strike);
+            let r = judge.dig(p.x() as usize, p.y() as usize, power)?;
+            report.strikes += 1;
+            report.cost += (solver.c() + power) as u64;
+            let broke = match r {
+                DigResult::NotBreak => false,
+                DigResult::Break => true,
+                DigResult::BreakAndFinish => {
+                    report.finished = true;
+                    return Ok(report);
+                }
+            };
+            solver.observe(p, s, power, broke);
+            s += power;
+            if broke {
+                break;
+            }
+        }
+    }
+    solver.update_cost();
+    Ok(report)
+}
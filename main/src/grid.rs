@@ -12,6 +12,14 @@ pub struct Grid<T> {
     data: Vec<T>,
 }
 
+// 上下左右の向き。neighbors はこの順に返す
+pub const DIRECTIONS: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+// (x, y) が盤面の中か
+pub fn in_bounds(x: i64, y: i64) -> bool {
+    0 <= x && x < N as i64 && 0 <= y && y < N as i64
+}
+
 impl Point {
     pub fn new(x: u32, y: u32) -> Point {
         Point { x, y }
@@ -29,21 +37,53 @@ impl Point {
         abs_diff(self.x, other.x) + abs_diff(self.y, other.y)
     }
 
-    pub fn neighbors(self) -> Vec<Point> {
-        let mut nei = Vec::with_capacity(4);
-        if self.x > 0 {
-            nei.push(Point::new(self.x - 1, self.y));
-        }
-        if self.x + 1 < N as u32 {
-            nei.push(Point::new(self.x + 1, self.y));
-        }
-        if self.y > 0 {
-            nei.push(Point::new(self.x, self.y - 1));
+    pub fn chebyshev(&self, other: &Point) -> u32 {
+        abs_diff(self.x, other.x).max(abs_diff(self.y, other.y))
+    }
+
+    // (dx, dy) だけずらしたマス。盤面の外なら None
+    pub fn offset(self, dx: i32, dy: i32) -> Option<Point> {
+        let x = self.x as i64 + dx as i64;
+        let y = self.y as i64 + dy as i64;
+        if in_bounds(x, y) {
+            Some(Point::new(x as u32, y as u32))
+        } else {
+            None
         }
-        if self.y + 1 < N as u32 {
-            nei.push(Point::new(self.x, self.y + 1));
+    }
+
+    // Grid の中での位置。y, x の順に並べたときの番号で、Dsu の頂点番号にも使う
+    pub fn index(self) -> usize {
+        self.y as usize * N + self.x as usize
+    }
+
+    // 盤面の中にある上下左右のマス。Vec を作らないので最短路の内側のループでも使える
+    pub fn neighbors(self) -> Neighbors {
+        Neighbors { p: self, k: 0 }
+    }
+}
+
+pub struct Neighbors {
+    p: Point,
+    k: usize,
+}
+
+impl Iterator for Neighbors {
+    type Item = Point;
+
+    fn next(&mut self) -> Option<Point> {
+        while self.k < DIRECTIONS.len() {
+            let (dx, dy) = DIRECTIONS[self.k];
+            self.k += 1;
+            if let Some(n) = self.p.offset(dx, dy) {
+                return Some(n);
+            }
         }
-        nei
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(DIRECTIONS.len() - self.k))
     }
 }
 
@@ -60,7 +100,40 @@ impl<T> Grid<T> {
         Grid { data }
     }
 
-    pub fn zip_map<U>(&mut self, other: &Grid<U>, mut f: impl FnMut(&mut T, &U) -> ()) {
+    // (マス, 値) を y, x の順に
+    pub fn iter(&self) -> impl Iterator<Item = (Point, &T)> {
+        self.data
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (Point::new((i % N) as u32, (i / N) as u32), t))
+    }
+
+    pub fn fold<B>(&self, init: B, f: impl FnMut(B, &T) -> B) -> B {
+        self.data.iter().fold(init, f)
+    }
+
+    pub fn row(&self, y: u32) -> &[T] {
+        let start = y as usize * N;
+        &self.data[start..start + N]
+    }
+
+    pub fn row_mut(&mut self, y: u32) -> &mut [T] {
+        let start = y as usize * N;
+        &mut self.data[start..start + N]
+    }
+
+    // 列はメモリ上で飛び飛びなので、スライスではなくイテレータで返す
+    pub fn column(&self, x: u32) -> impl Iterator<Item = &T> {
+        self.data[x as usize..].iter().step_by(N)
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Grid<U> {
+        Grid {
+            data: self.data.into_iter().map(f).collect(),
+        }
+    }
+
+    pub fn zip_map<U>(&mut self, other: &Grid<U>, mut f: impl FnMut(&mut T, &U)) {
         self.data
             .iter_mut()
             .zip(other.data.iter())
@@ -73,12 +146,44 @@ impl<T> Index<Point> for Grid<T> {
     type Output = T;
 
     fn index(&self, index: Point) -> &Self::Output {
-        &self.data[(index.y * N as u32 + index.x) as usize]
+        &self.data[index.index()]
     }
 }
 
 impl<T> IndexMut<Point> for Grid<T> {
     fn index_mut(&mut self, index: Point) -> &mut Self::Output {
-        &mut self.data[(index.y * N as u32 + index.x) as usize]
+        &mut self.data[index.index()]
+    }
+}
+
+#[test]
+fn test_grid() {
+    let corner = Point::new(0, 0);
+    assert_eq!(
+        corner.neighbors().collect::<Vec<_>>(),
+        vec![Point::new(1, 0), Point::new(0, 1)]
+    );
+    let p = Point::new(5, 7);
+    assert_eq!(p.neighbors().count(), 4);
+    assert!(p.neighbors().all(|n| n.manhattan(&p) == 1));
+    assert_eq!(p.chebyshev(&Point::new(8, 3)), 4);
+    assert_eq!(corner.offset(-1, 0), None);
+    assert_eq!(p.offset(2, -3), Some(Point::new(7, 4)));
+    assert!(!in_bounds(N as i64, 0));
+    assert_eq!(p.index(), 7 * N + 5);
+
+    let mut grid = Grid::new(0);
+    for (p, v) in grid.iter() {
+        assert_eq!(*v, 0);
+        assert!(in_bounds(p.x() as i64, p.y() as i64));
     }
+    grid[Point::new(3, 2)] = 5;
+    grid[Point::new(3, 4)] = 7;
+    assert_eq!(grid.row(2).iter().sum::<i32>(), 5);
+    assert_eq!(grid.column(3).sum::<i32>(), 12);
+    assert_eq!(grid.fold(0, |acc, &v| acc.max(v)), 7);
+    assert_eq!(
+        grid.iter().find(|&(_, &v)| v == 7).map(|(p, _)| p),
+        Some(Point::new(3, 4))
+    );
 }
@@ -0,0 +1,176 @@
+use crate::{
+    ensemble::Interval,
+    predict_h::{LandGen, PARAM_RANGE},
+    Point, N,
+};
+use rand::Rng;
+
+// 値域を伸ばすときの最小値・最大値をこの間隔の格子で近似する
+const RANGE_STRIDE: usize = 8;
+// 観測区間からのずれ (対数) の許容幅
+const LOG_SIGMA: f64 = 0.3;
+// 分布を更新するときに前の分布を残す割合
+const SMOOTHING: f64 = 0.3;
+
+#[derive(Debug, Clone)]
+pub struct InferConfig {
+    // 試す Perlin の種の数
+    pub seeds: usize,
+    // 種ごとの 1 世代の個体数と、そのうち次の分布を決める上位の数
+    pub population: usize,
+    pub elite: usize,
+    pub iterations: usize,
+    // 返す LandGen の数
+    pub posterior: usize,
+}
+
+impl Default for InferConfig {
+    fn default() -> InferConfig {
+        InferConfig {
+            seeds: 16,
+            population: 40,
+            elite: 8,
+            iterations: 8,
+            posterior: 4,
+        }
+    }
+}
+
+// 観測区間とのずれの二乗和。小さいほどよく合っている
+pub fn loss(gen: &LandGen, observations: &[(Point, Interval)]) -> f64 {
+    let mut min = std::f64::MAX;
+    let mut max = std::f64::MIN;
+    for r in (0..N).step_by(RANGE_STRIDE) {
+        for c in (0..N).step_by(RANGE_STRIDE) {
+            let v = gen.get(r, c);
+            min = min.min(v);
+            max = max.max(v);
+        }
+    }
+    if max <= min {
+        return std::f64::MAX;
+    }
+    observations
+        .iter()
+        .map(|&(p, interval)| {
+            let v = gen.get(p.x() as usize, p.y() as usize);
+            let h = (10.0 + (v - min) / (max - min) * (5000.0 - 10.0)).max(10.0);
+            let d = log_distance(h.ln(), interval) / LOG_SIGMA;
+            d * d
+        })
+        .sum()
+}
+
+fn log_distance(log_h: f64, interval: Interval) -> f64 {
+    let lower = ((interval.lower + 1) as f64).ln();
+    if log_h < lower {
+        return lower - log_h;
+    }
+    match interval.upper {
+        Some(upper) if (upper as f64).ln() < log_h => log_h - (upper as f64).ln(),
+        _ => 0.0,
+    }
+}
+
+fn normal<R: Rng>(rng: &mut R) -> f64 {
+    // Box-Muller
+    let u = 1.0 - rng.gen::<f64>();
+    let v = rng.gen::<f64>();
+    (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+}
+
+// 1 つの種について、パラメータの正規分布を上位の個体に寄せていく交差エントロピー法
+fn cross_entropy<R: Rng>(
+    rng: &mut R,
+    seed: u32,
+    observations: &[(Point, Interval)],
+    config: &InferConfig,
+) -> (f64, [f64; 7]) {
+    let mut mean = [0.0; 7];
+    let mut sd = [0.0; 7];
+    for (i, &(lo, hi)) in PARAM_RANGE.iter().enumerate() {
+        mean[i] = (lo + hi) / 2.0;
+        sd[i] = (hi - lo) / 2.0;
+    }
+    let mut best = (std::f64::MAX, mean);
+    for _ in 0..config.iterations {
+        let mut population = (0..config.population)
+            .map(|_| {
+                let mut params = [0.0; 7];
+                for (i, &(lo, hi)) in PARAM_RANGE.iter().enumerate() {
+                    params[i] = (mean[i] + sd[i] * normal(rng)).max(lo).min(hi);
+                }
+                let l = loss(&LandGen::with_params(seed, params), observations);
+                (l, params)
+            })
+            .collect::<Vec<_>>();
+        population.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        if population[0].0 < best.0 {
+            best = population[0];
+        }
+        let elite = &population[..config.elite.min(population.len())];
+        for i in 0..7 {
+            let m = elite.iter().map(|e| e.1[i]).sum::<f64>() / elite.len() as f64;
+            let v = elite.iter().map(|e| (e.1[i] - m).powi(2)).sum::<f64>() / elite.len() as f64;
+            mean[i] = SMOOTHING * mean[i] + (1.0 - SMOOTHING) * m;
+            sd[i] = SMOOTHING * sd[i] + (1.0 - SMOOTHING) * v.sqrt();
+        }
+    }
+    best
+}
+
+// 観測によく合う LandGen を、種ごとに最適化したものの上位から config.posterior 個返す
+pub fn infer<R: Rng>(
+    rng: &mut R,
+    observations: &[(Point, Interval)],
+    config: &InferConfig,
+) -> Vec<LandGen> {
+    if observations.is_empty() {
+        return Vec::new();
+    }
+    let mut fitted = (0..config.seeds)
+        .map(|_| {
+            let seed = rng.gen();
+            let (l, params) = cross_entropy(rng, seed, observations, config);
+            (l, seed, params)
+        })
+        .collect::<Vec<_>>();
+    fitted.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    fitted
+        .into_iter()
+        .take(config.posterior)
+        .map(|(_, seed, params)| LandGen::with_params(seed, params))
+        .collect()
+}
+
+#[test]
+fn test_cross_entropy() {
+    use rand_pcg::Mcg128Xsl64;
+
+    // 正しい種が分かっていれば、観測に合うパラメータが見つかる
+    let mut rng = Mcg128Xsl64::new(1);
+    let truth = LandGen::new(&mut rng);
+    let h = truth.to_grid();
+    let mut observations = Vec::new();
+    for x in (5..N as u32).step_by(20) {
+        for y in (5..N as u32).step_by(20) {
+            let p = Point::new(x, y);
+            let lower = h[p] / 100 * 100;
+            let interval = Interval {
+                lower,
+                upper: Some(lower + 100),
+            };
+            observations.push((p, interval));
+        }
+    }
+    let config = InferConfig::default();
+    let random = (0..config.population)
+        .map(|_| {
+            let gen = LandGen::with_params(truth.seed(), LandGen::new(&mut rng).params());
+            loss(&gen, &observations)
+        })
+        .fold(std::f64::MAX, f64::min);
+    let (fitted, _) = cross_entropy(&mut rng, truth.seed(), &observations, &config);
+    assert!(fitted < random);
+    assert!(fitted < observations.len() as f64);
+}
@@ -1,15 +1,82 @@
-use std::io::{BufRead, Stdout, Write};
+use crate::{dsu::Dsu, Grid, Point, N};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufWriter, Stdout, Write},
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum DigResult {
     NotBreak,
     Break,
     BreakAndFinish,
-    Error,
+}
+
+#[derive(Debug)]
+pub enum JudgeError {
+    // 書き込みか読み込みに失敗した
+    Io(io::Error),
+    // ジャッジが応答を返さずに閉じた
+    Eof,
+    // 知らない応答が返ってきた
+    Malformed(String),
+    // ジャッジが -1 を返した (不正な掘削や、終わった後の掘削)
+    Rejected,
+    // 再生中に記録と違う掘り方をした。記録が尽きていれば expected は None
+    Diverged {
+        turn: usize,
+        expected: Option<(usize, usize, u32)>,
+        actual: (usize, usize, u32),
+    },
+}
+
+impl fmt::Display for JudgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JudgeError::Io(e) => write!(f, "I/O error: {}", e),
+            JudgeError::Eof => write!(f, "unexpected EOF"),
+            JudgeError::Malformed(s) => write!(f, "malformed response: {:?}", s),
+            JudgeError::Rejected => write!(f, "rejected by judge"),
+            JudgeError::Diverged {
+                turn,
+                expected: Some(e),
+                actual: a,
+            } => write!(
+                f,
+                "diverged at turn {}: expected {} {} {}, got {} {} {}",
+                turn, e.0, e.1, e.2, a.0, a.1, a.2
+            ),
+            JudgeError::Diverged {
+                turn,
+                expected: None,
+                actual: a,
+            } => write!(
+                f,
+                "diverged at turn {}: transcript ended, got {} {} {}",
+                turn, a.0, a.1, a.2
+            ),
+        }
+    }
+}
+
+impl std::error::Error for JudgeError {}
+
+impl From<io::Error> for JudgeError {
+    fn from(e: io::Error) -> JudgeError {
+        JudgeError::Io(e)
+    }
 }
 
 pub trait Judge {
-    fn dig(&mut self, x: usize, y: usize, p: u32) -> DigResult;
+    fn dig(&mut self, x: usize, y: usize, p: u32) -> Result<DigResult, JudgeError>;
+
+    // "# " で始まる注釈の行を出力に書く。出力のないジャッジでは捨てる
+    fn comment(&mut self, _args: fmt::Arguments) -> Result<(), JudgeError> {
+        Ok(())
+    }
 }
 
 pub struct ExternalJudge<R> {
@@ -27,17 +94,396 @@ impl<R> ExternalJudge<R> {
 }
 
 impl<R: BufRead> Judge for ExternalJudge<R> {
-    fn dig(&mut self, x: usize, y: usize, p: u32) -> DigResult {
-        writeln!(self.stdout, "{} {} {}", x, y, p).unwrap();
-        self.stdout.flush().unwrap();
+    fn dig(&mut self, x: usize, y: usize, p: u32) -> Result<DigResult, JudgeError> {
+        writeln!(self.stdout, "{} {} {}", x, y, p)?;
+        self.stdout.flush()?;
         let mut buf = String::new();
-        self.stdin.read_line(&mut buf).unwrap();
+        if self.stdin.read_line(&mut buf)? == 0 {
+            return Err(JudgeError::Eof);
+        }
         match buf.trim() {
-            "0" => DigResult::NotBreak,
-            "1" => DigResult::Break,
-            "2" => DigResult::BreakAndFinish,
-            "-1" => DigResult::Error,
-            _ => unreachable!(),
+            "0" => Ok(DigResult::NotBreak),
+            "1" => Ok(DigResult::Break),
+            "2" => Ok(DigResult::BreakAndFinish),
+            "-1" => Err(JudgeError::Rejected),
+            _ => Err(JudgeError::Malformed(buf)),
+        }
+    }
+
+    fn comment(&mut self, args: fmt::Arguments) -> Result<(), JudgeError> {
+        writeln!(self.stdout, "# {}", args)?;
+        Ok(())
+    }
+}
+
+fn response(r: &Result<DigResult, JudgeError>) -> Option<&'static str> {
+    match r {
+        Ok(DigResult::NotBreak) => Some("0"),
+        Ok(DigResult::Break) => Some("1"),
+        Ok(DigResult::BreakAndFinish) => Some("2"),
+        Err(JudgeError::Rejected) => Some("-1"),
+        Err(_) => None,
+    }
+}
+
+// 掘削 1 回の往復にかかった時間を測るジャッジ
+pub struct TimedJudge<J> {
+    judge: J,
+    digs: u32,
+    total: Duration,
+}
+
+impl<J: Judge> TimedJudge<J> {
+    pub fn new(judge: J) -> TimedJudge<J> {
+        TimedJudge {
+            judge,
+            digs: 0,
+            total: Duration::from_secs(0),
+        }
+    }
+
+    pub fn digs(&self) -> u32 {
+        self.digs
+    }
+
+    pub fn into_inner(self) -> J {
+        self.judge
+    }
+
+    // 平均の往復時間。まだ一度も掘っていなければ None
+    pub fn latency(&self) -> Option<Duration> {
+        if self.digs == 0 {
+            None
+        } else {
+            Some(self.total / self.digs)
+        }
+    }
+}
+
+impl<J: Judge> Judge for TimedJudge<J> {
+    fn dig(&mut self, x: usize, y: usize, p: u32) -> Result<DigResult, JudgeError> {
+        let start = Instant::now();
+        let r = self.judge.dig(x, y, p);
+        self.digs += 1;
+        self.total += start.elapsed();
+        r
+    }
+
+    fn comment(&mut self, args: fmt::Arguments) -> Result<(), JudgeError> {
+        self.judge.comment(args)
+    }
+}
+
+// 掘削とその応答を 1 行ずつ "x y power 応答" の形で書き出すジャッジ
+pub struct RecordingJudge<J, W> {
+    judge: J,
+    out: W,
+}
+
+impl<J: Judge, W: Write> RecordingJudge<J, W> {
+    pub fn new(judge: J, out: W) -> RecordingJudge<J, W> {
+        RecordingJudge { judge, out }
+    }
+
+    pub fn into_inner(self) -> (J, W) {
+        (self.judge, self.out)
+    }
+}
+
+impl<J: Judge> RecordingJudge<J, BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(
+        judge: J,
+        path: P,
+    ) -> io::Result<RecordingJudge<J, BufWriter<File>>> {
+        Ok(RecordingJudge::new(
+            judge,
+            BufWriter::new(File::create(path)?),
+        ))
+    }
+}
+
+impl<J: Judge, W: Write> Judge for RecordingJudge<J, W> {
+    fn dig(&mut self, x: usize, y: usize, p: u32) -> Result<DigResult, JudgeError> {
+        let r = self.judge.dig(x, y, p);
+        match (response(&r), &r) {
+            (Some(token), _) => writeln!(self.out, "{} {} {} {}", x, y, p, token)?,
+            (None, Err(e)) => writeln!(self.out, "# {} {} {} {}", x, y, p, e)?,
+            (None, Ok(_)) => unreachable!(),
+        }
+        // 途中で落ちても手前までは残るように毎回書き出す
+        self.out.flush()?;
+        r
+    }
+
+    fn comment(&mut self, args: fmt::Arguments) -> Result<(), JudgeError> {
+        self.judge.comment(args)
+    }
+}
+
+// RecordingJudge の記録から応答を返すジャッジ。記録と違う掘り方をしたらそこで止める
+pub struct ReplayJudge {
+    log: Vec<((usize, usize, u32), String)>,
+    turn: usize,
+}
+
+impl ReplayJudge {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ReplayJudge, String> {
+        let s = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        s.parse()
+    }
+
+    // 次に応答する掘削の番号 (0 始まり)
+    pub fn turn(&self) -> usize {
+        self.turn
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.turn == self.log.len()
+    }
+}
+
+impl FromStr for ReplayJudge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ReplayJudge, String> {
+        let mut log = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let parse_error = || format!("line {}: Parse error: {}", i + 1, line);
+            if words.len() != 4 {
+                return Err(parse_error());
+            }
+            let x = words[0].parse().map_err(|_| parse_error())?;
+            let y = words[1].parse().map_err(|_| parse_error())?;
+            let p = words[2].parse().map_err(|_| parse_error())?;
+            log.push(((x, y, p), words[3].to_string()));
+        }
+        Ok(ReplayJudge { log, turn: 0 })
+    }
+}
+
+impl Judge for ReplayJudge {
+    fn dig(&mut self, x: usize, y: usize, p: u32) -> Result<DigResult, JudgeError> {
+        let actual = (x, y, p);
+        let expected = self.log.get(self.turn).map(|e| e.0);
+        if expected != Some(actual) {
+            return Err(JudgeError::Diverged {
+                turn: self.turn,
+                expected,
+                actual,
+            });
+        }
+        let token = &self.log[self.turn].1;
+        self.turn += 1;
+        match token.as_str() {
+            "0" => Ok(DigResult::NotBreak),
+            "1" => Ok(DigResult::Break),
+            "2" => Ok(DigResult::BreakAndFinish),
+            "-1" => Err(JudgeError::Rejected),
+            _ => Err(JudgeError::Malformed(token.clone())),
+        }
+    }
+
+    // 再生しても注釈は見たいので、本番と同じく標準出力に書く
+    fn comment(&mut self, args: fmt::Arguments) -> Result<(), JudgeError> {
+        writeln!(io::stdout(), "# {}", args)?;
+        Ok(())
+    }
+}
+
+// tools の入力ファイル (硬さ付き) をそのまま読んだもの
+#[derive(Debug, Clone)]
+pub struct TesterInput {
+    pub h: Grid<u32>,
+    pub c: u32,
+    pub water: Vec<(u32, u32)>,
+    pub house: Vec<(u32, u32)>,
+}
+
+impl TesterInput {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<TesterInput, String> {
+        let s = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        s.parse()
+    }
+}
+
+impl FromStr for TesterInput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TesterInput, String> {
+        let mut words = s.split_whitespace();
+        let mut next = || -> Result<u32, String> {
+            let w = words.next().ok_or_else(|| "Unexpected EOF".to_string())?;
+            w.parse().map_err(|_| format!("Parse error: {}", w))
+        };
+        let n = next()?;
+        if n != N as u32 {
+            return Err(format!("N must be {}: {}", N, n));
+        }
+        let w = next()?;
+        let k = next()?;
+        let c = next()?;
+        let mut h = Grid::new(0);
+        for x in 0..N as u32 {
+            for y in 0..N as u32 {
+                h[Point::new(x, y)] = next()?;
+            }
+        }
+        let mut water = Vec::with_capacity(w as usize);
+        for _ in 0..w {
+            water.push((next()?, next()?));
+        }
+        let mut house = Vec::with_capacity(k as usize);
+        for _ in 0..k {
+            house.push((next()?, next()?));
+        }
+        Ok(TesterInput { h, c, water, house })
+    }
+}
+
+// tools の Sim と同じルールで、プロセス内で掘った結果を返すジャッジ
+pub struct LocalJudge {
+    input: TesterInput,
+    rest: Grid<u32>,
+    dsu: Dsu,
+    cost: u64,
+    finished: bool,
+}
+
+impl LocalJudge {
+    pub fn new(input: TesterInput) -> LocalJudge {
+        LocalJudge {
+            rest: input.h.clone(),
+            input,
+            dsu: Dsu::new(N * N),
+            cost: 0,
+            finished: false,
+        }
+    }
+
+    pub fn input(&self) -> &TesterInput {
+        &self.input
+    }
+
+    // 本当の硬さ
+    pub fn hardness(&self, p: Point) -> u32 {
+        self.input.h[p]
+    }
+
+    pub fn is_broken(&self, p: Point) -> bool {
+        self.rest[p] == 0
+    }
+
+    // ここまでに払ったコスト。全部つながった後はそのままスコアになる
+    pub fn cost(&self) -> u64 {
+        self.cost
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn connected(&mut self) -> bool {
+        let water = self
+            .input
+            .water
+            .iter()
+            .map(|&(x, y)| Point::new(x, y).index())
+            .collect::<Vec<_>>();
+        let dsu = &mut self.dsu;
+        let rest = &self.rest;
+        self.input.house.iter().all(|&(x, y)| {
+            let h = Point::new(x, y);
+            rest[h] == 0 && water.iter().any(|&w| dsu.same(h.index(), w))
+        })
+    }
+}
+
+impl Judge for LocalJudge {
+    fn dig(&mut self, x: usize, y: usize, p: u32) -> Result<DigResult, JudgeError> {
+        if self.finished || N <= x || N <= y || !(1..=5000).contains(&p) {
+            return Err(JudgeError::Rejected);
+        }
+        let q = Point::new(x as u32, y as u32);
+        if self.rest[q] == 0 {
+            return Err(JudgeError::Rejected);
         }
+        self.cost += (self.input.c + p) as u64;
+        self.rest[q] = self.rest[q].saturating_sub(p);
+        if self.rest[q] > 0 {
+            return Ok(DigResult::NotBreak);
+        }
+        for n in q.neighbors() {
+            if self.rest[n] == 0 {
+                self.dsu.merge(q.index(), n.index());
+            }
+        }
+        if self.connected() {
+            self.finished = true;
+            Ok(DigResult::BreakAndFinish)
+        } else {
+            Ok(DigResult::Break)
+        }
+    }
+}
+
+#[test]
+fn test_local_judge() {
+    let mut s = format!("{} 1 1 1\n", N);
+    for x in 0..N {
+        let row = (0..N).map(|y| (x + y + 10).to_string()).collect::<Vec<_>>();
+        s += &row.join(" ");
+        s += "\n";
+    }
+    s += "0 0\n0 2\n";
+    let input = s.parse::<TesterInput>().unwrap();
+    assert_eq!(input.h[Point::new(3, 5)], 18);
+    let mut judge = LocalJudge::new(input);
+    assert_eq!(judge.dig(0, 0, 5).unwrap(), DigResult::NotBreak);
+    assert_eq!(judge.dig(0, 0, 5).unwrap(), DigResult::Break);
+    assert_eq!(judge.dig(0, 2, 12).unwrap(), DigResult::Break);
+    assert!(judge.dig(0, 2, 1).is_err());
+    assert_eq!(judge.dig(0, 1, 11).unwrap(), DigResult::BreakAndFinish);
+    assert_eq!(judge.cost(), (1 + 5) + (1 + 5) + (1 + 12) + (1 + 11));
+    assert!(judge.dig(1, 1, 100).is_err());
+}
+
+#[test]
+fn test_replay_judge() {
+    let mut s = format!("{} 1 1 1\n", N);
+    for _ in 0..N {
+        s += &vec!["10"; N].join(" ");
+        s += "\n";
+    }
+    s += "0 0\n0 1\n";
+    let judge = LocalJudge::new(s.parse().unwrap());
+    let mut judge = RecordingJudge::new(judge, Vec::new());
+    assert_eq!(judge.dig(0, 0, 5).unwrap(), DigResult::NotBreak);
+    assert_eq!(judge.dig(0, 0, 5).unwrap(), DigResult::Break);
+    assert!(judge.dig(0, 0, 5).is_err());
+    assert_eq!(judge.dig(0, 1, 10).unwrap(), DigResult::BreakAndFinish);
+    let (_, log) = judge.into_inner();
+    let log = String::from_utf8(log).unwrap();
+    assert_eq!(log, "0 0 5 0\n0 0 5 1\n0 0 5 -1\n0 1 10 2\n");
+
+    let mut replay = log.parse::<ReplayJudge>().unwrap();
+    assert_eq!(replay.dig(0, 0, 5).unwrap(), DigResult::NotBreak);
+    assert_eq!(replay.dig(0, 0, 5).unwrap(), DigResult::Break);
+    assert!(matches!(replay.dig(0, 0, 5), Err(JudgeError::Rejected)));
+    match replay.dig(0, 1, 20) {
+        Err(JudgeError::Diverged {
+            turn: 3,
+            expected: Some((0, 1, 10)),
+            actual: (0, 1, 20),
+        }) => (),
+        r => panic!("{:?}", r),
     }
+    assert_eq!(replay.dig(0, 1, 10).unwrap(), DigResult::BreakAndFinish);
+    assert!(replay.is_finished());
 }
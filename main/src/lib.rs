@@ -1,8 +1,31 @@
+// 提出先の古い Rust には u32::MAX などの関連定数や clamp, abs_diff, div_ceil, is_multiple_of がないので、
+// それらを勧める lint は切っておく
+#![allow(
+    clippy::legacy_numeric_constants,
+    clippy::manual_clamp,
+    clippy::manual_abs_diff,
+    clippy::manual_div_ceil,
+    clippy::manual_is_multiple_of
+)]
+
+pub mod anneal;
+pub mod config;
 pub mod dsu;
+pub mod ensemble;
+pub mod estimate;
+pub mod explore;
 mod grid;
+pub mod infer;
 pub mod judge;
+pub mod network;
+pub mod parallel;
+pub mod power;
 pub mod predict_h;
+pub mod risk;
+pub mod shortest_path;
 pub mod solver;
+pub mod steiner;
+pub mod time_keeper;
 
 pub use crate::grid::*;
 
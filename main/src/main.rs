@@ -1,10 +1,22 @@
 use ahc018::{
-    judge::{DigResult, ExternalJudge, Judge},
+    anneal::AnnealConfig,
+    config::SolverConfig,
+    explore::{explore, ExploreConfig},
+    infer::InferConfig,
+    judge::{DigResult, ExternalJudge, Judge, JudgeError, RecordingJudge, ReplayJudge, TimedJudge},
     solver::Solver,
-    SetMinMax, N,
+    time_keeper::{Pace, TimeKeeper},
+    Point, N,
 };
 use rand_pcg::Mcg128Xsl64;
-use std::io::{stdin, BufRead};
+use std::{
+    collections::VecDeque,
+    io::{self, stdin, stdout, BufRead, Write},
+    time::{Duration, Instant},
+};
+
+// 1 マスを壊すまでに掘る回数の見積もり
+const STRIKES_PER_CELL: usize = 4;
 
 struct Input {
     n: usize,
@@ -46,41 +58,302 @@ impl Input {
     }
 }
 
-fn main() {
-    let mut stdin = std::io::BufReader::new(stdin());
-    let input = Input::new(&mut stdin);
-    assert_eq!(input.n, N);
+// ジャッジとのやり取りが壊れたら、出力に残せるなら残して終わる
+fn report_error(e: &JudgeError) {
+    let _ = writeln!(stdout(), "# judge error: {}", e);
+    eprintln!("judge error: {}", e);
+}
+
+// 掘りかけの p を残りの計画ごと掘り切るより、p を使わない計画の方が安ければそれを返す。
+// 掘り切るコストは p の累積パワーを踏まえた見積もりなので、もう使ったパワーは含まない。
+// 解き直しの時間は pace に記録し、平均で見て deadline までに 1 回も収まらなければ解き直さない
+fn reroute(
+    solver: &mut Solver,
+    rng: &mut Mcg128Xsl64,
+    config: &SolverConfig,
+    p: Point,
+    plan: &VecDeque<Point>,
+    deadline: Instant,
+    pace: &mut Pace,
+) -> Option<Vec<Point>> {
+    if pace.affordable(deadline) == 0 {
+        return None;
+    }
+    let start = Instant::now();
+    solver.update_cost();
+    let (next, cost) = solver.plan(rng, config.replan_restarts, deadline);
+    pace.record(start.elapsed());
+    if next.contains(&p) {
+        return None;
+    }
+    let finish = solver.plan_cost(std::iter::once(p).chain(plan.iter().copied()));
+    if (cost as f64) < finish as f64 * (1.0 - config.switch_margin) {
+        Some(next)
+    } else {
+        None
+    }
+}
 
-    let mut rng = Mcg128Xsl64::new(1);
-
-    let mut solver = Solver::new(&mut rng, &input.water, &input.house, input.c);
-    let ans = {
-        let mut best = Vec::new();
-        let mut best_cost = std::u64::MAX;
-        for _ in 0..10 {
-            solver.reset();
-            let (ans, cost) = solver.solve(&mut rng);
-            if best_cost.setmin(cost) {
-                best = ans;
+fn run<J: Judge>(
+    judge: &mut TimedJudge<J>,
+    solver: &mut Solver,
+    rng: &mut Mcg128Xsl64,
+    config: &SolverConfig,
+    time: &TimeKeeper,
+) -> Result<(), JudgeError> {
+    let stats = solver.sample_stats();
+    judge.comment(format_args!(
+        "samples {} attempts {} accepted {} fallbacks {} timeouts {} ({:.3}s)",
+        solver.samples(),
+        stats.attempts,
+        stats.accepted,
+        stats.fallbacks,
+        stats.timeouts,
+        stats.elapsed.as_secs_f64()
+    ))?;
+    if config.explore {
+        let report = explore(judge, solver, &ExploreConfig::new(solver.c(), config))?;
+        judge.comment(format_args!(
+            "probe {} cells, {} strikes, cost {}",
+            report.probes, report.strikes, report.cost
+        ))?;
+        eprintln!(
+            "probe {} cells, {} strikes, cost {}",
+            report.probes, report.strikes, report.cost
+        );
+        if report.finished {
+            return Ok(());
+        }
+    }
+    if config.infer > 0 {
+        let infer_config = InferConfig {
+            seeds: config.infer_seeds,
+            posterior: config.infer,
+            ..InferConfig::default()
+        };
+        let n = solver.infer(rng, &infer_config, config.infer_weight);
+        judge.comment(format_args!("inferred {} maps", n))?;
+    }
+    // まだ道が決まっていないので、各家から一番近い水源までのマンハッタン距離で掘る回数を見積もる
+    let digs = solver
+        .house()
+        .iter()
+        .map(|h| solver.water().iter().map(|w| w.manhattan(h)).min().unwrap())
+        .sum::<u32>() as usize
+        * STRIKES_PER_CELL;
+    let mut plan = {
+        let deadline = time.reserve(digs, judge.latency());
+        let (restart, restart_cost) = solver.plan(rng, config.restarts, deadline);
+        let (tree, tree_cost) = solver.steiner();
+        judge.comment(format_args!(
+            "restart {} steiner {}",
+            restart_cost, tree_cost
+        ))?;
+        let plan = if (tree_cost as f64) < restart_cost as f64 * (1.0 - config.switch_margin) {
+            tree
+        } else {
+            restart
+        };
+        if config.anneal > 0 {
+            let anneal_config = AnnealConfig {
+                iterations: config.anneal,
+                start_temperature: config.anneal_temperature,
+                ..AnnealConfig::default()
+            };
+            let (annealed, cost) = solver.anneal(rng, &plan, &anneal_config, Some(deadline));
+            judge.comment(format_args!("anneal {}", cost))?;
+            if cost < solver.plan_cost(plan.iter().copied()) {
+                annealed
+            } else {
+                plan
             }
+        } else {
+            plan
         }
-        best
-    };
+    }
+    .into_iter()
+    .collect::<VecDeque<_>>();
 
-    let mut judge = ExternalJudge::new(stdin);
-    for p in ans {
-        let mut s = 0;
+    // 立て直しは max_replans 回までなので、最初の計画の長さに合わせて間隔を広げ、終わりの方まで行き渡らせる
+    let replan_interval = if config.replan_interval == 0 || config.max_replans == 0 {
+        0
+    } else {
+        config
+            .replan_interval
+            .max(plan.len() / config.max_replans + 1)
+    };
+    // 立て直しと迂回の解き直しは同じくらいかかるので、時間の見積もりを共有する
+    let mut pace = Pace::new();
+    let mut replans = 0;
+    let mut reroutes = 0;
+    let mut breaks = 0;
+    'cells: loop {
+        let p = match plan.pop_front() {
+            Some(p) => p,
+            None => {
+                // 計画を掘り終えてもジャッジが終わらなければ、実際に壊したマスから足りない道を足す
+                let repair = solver.repair_plan();
+                if repair.is_empty() {
+                    break;
+                }
+                judge.comment(format_args!("repair {} cells", repair.len()))?;
+                plan = repair.into_iter().collect();
+                continue;
+            }
+        };
+        let mut s = solver.damage(p);
+        // 見積もりの硬さをこれだけ超えたら、掘り切るか迂回するかを考え直す
+        let mut limit = match (config.abandon_margin, solver.predicted_hardness(p)) {
+            (Some(m), Some(h)) => Some((h as f64 * (1.0 + m)) as u32),
+            _ => None,
+        };
         loop {
-            let (power, cost) = solver.guess_power(p, s);
-            println!("# {} {}", power, cost);
-            let r = judge.dig(p.x() as usize, p.y() as usize, power);
+            let (power, _) = solver.guess_power(p, s);
+            let broke = match judge.dig(p.x() as usize, p.y() as usize, power)? {
+                DigResult::NotBreak => false,
+                DigResult::Break => true,
+                DigResult::BreakAndFinish => return Ok(()),
+            };
+            solver.observe(p, s, power, broke);
             s += power;
-            match r {
-                DigResult::NotBreak => continue,
-                DigResult::Break => break,
-                DigResult::BreakAndFinish | DigResult::Error => return,
+            if broke {
+                break;
+            }
+            if let (Some(m), Some(l)) = (config.abandon_margin, limit) {
+                if l < s && reroutes < config.max_reroutes {
+                    limit = Some((s as f64 * (1.0 + m)) as u32);
+                    reroutes += 1;
+                    let deadline =
+                        time.reserve((plan.len() + 1) * STRIKES_PER_CELL, judge.latency());
+                    if let Some(next) = reroute(solver, rng, config, p, &plan, deadline, &mut pace)
+                    {
+                        judge.comment(format_args!("abandon {} {} at {}", p.x(), p.y(), s))?;
+                        plan = next.into_iter().collect();
+                        continue 'cells;
+                    }
+                }
+            }
+        }
+        breaks += 1;
+        let deadline = time.reserve(plan.len() * STRIKES_PER_CELL, judge.latency());
+        if replan_interval > 0
+            && breaks % replan_interval == 0
+            && replans < config.max_replans
+            && pace.affordable(deadline) > 0
+        {
+            replans += 1;
+            judge.comment(format_args!("replan {}", replans))?;
+            let start = Instant::now();
+            solver.update_cost();
+            let (next, cost) = solver.plan(rng, config.replan_restarts, deadline);
+            pace.record(start.elapsed());
+            let cur = solver.plan_cost(plan.iter().copied());
+            if (cost as f64) < cur as f64 * (1.0 - config.switch_margin) {
+                plan = next.into_iter().collect();
             }
         }
     }
     eprintln!("答えが足らん");
+    Ok(())
+}
+
+// record が指定されていればやり取りを書き出しながら run する
+fn solve<J: Judge>(
+    judge: J,
+    solver: &mut Solver,
+    rng: &mut Mcg128Xsl64,
+    config: &SolverConfig,
+    time: &TimeKeeper,
+) -> Result<(), JudgeError> {
+    match &config.record {
+        Some(path) => {
+            let judge = RecordingJudge::create(judge, path)?;
+            run(&mut TimedJudge::new(judge), solver, rng, config, time)
+        }
+        None => run(&mut TimedJudge::new(judge), solver, rng, config, time),
+    }
+}
+
+fn main() {
+    let config = SolverConfig::from_args();
+    let mut stdin = std::io::BufReader::new(stdin());
+    let input = Input::new(&mut stdin);
+    assert_eq!(input.n, N);
+
+    let time = TimeKeeper::new(Duration::from_millis(config.time_limit_millis));
+    let mut rng = Mcg128Xsl64::new(config.seed as u128);
+
+    let mut solver = Solver::new(
+        &mut rng,
+        &input.water,
+        &input.house,
+        input.c,
+        &config,
+        &time,
+    );
+    let result = match &config.replay {
+        Some(path) => match ReplayJudge::load(path) {
+            Ok(judge) => solve(judge, &mut solver, &mut rng, &config, &time),
+            Err(e) => Err(JudgeError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                e,
+            ))),
+        },
+        None => solve(
+            ExternalJudge::new(stdin),
+            &mut solver,
+            &mut rng,
+            &config,
+            &time,
+        ),
+    };
+    if let Err(e) = result {
+        report_error(&e);
+    }
+    eprintln!("time {:.3}s", time.elapsed().as_secs_f64());
+}
+
+#[test]
+fn test_record_and_replay() {
+    use ahc018::judge::{LocalJudge, TesterInput};
+
+    let mut s = format!("{} 2 3 4\n", N);
+    for x in 0..N {
+        let row = (0..N)
+            .map(|y| ((x * 37 + y * 11) % 300 + 10).to_string())
+            .collect::<Vec<_>>();
+        s += &row.join(" ");
+        s += "\n";
+    }
+    s += "10 10\n150 120\n30 25\n140 160\n60 40\n";
+    let input = s.parse::<TesterInput>().unwrap();
+    let config = SolverConfig {
+        time_limit_millis: 600000,
+        max_attempts: 100,
+        ..SolverConfig::default()
+    };
+
+    fn replay<J: Judge>(
+        judge: J,
+        input: &TesterInput,
+        config: &SolverConfig,
+    ) -> (Result<(), JudgeError>, J) {
+        let time = TimeKeeper::new(Duration::from_millis(config.time_limit_millis));
+        let mut rng = Mcg128Xsl64::new(config.seed as u128);
+        let mut solver = Solver::new(&mut rng, &input.water, &input.house, input.c, config, &time);
+        let mut judge = TimedJudge::new(judge);
+        let result = run(&mut judge, &mut solver, &mut rng, config, &time);
+        (result, judge.into_inner())
+    }
+
+    // 回数の上限だけで決まるので、記録した応答をそのまま返せば同じ掘り方になる
+    let judge = RecordingJudge::new(LocalJudge::new(input.clone()), Vec::new());
+    let (result, judge) = replay(judge, &input, &config);
+    assert!(result.is_ok());
+    let (judge, log) = judge.into_inner();
+    assert!(judge.is_finished());
+    let log = String::from_utf8(log).unwrap();
+    let (result, judge) = replay(log.parse::<ReplayJudge>().unwrap(), &input, &config);
+    assert!(result.is_ok());
+    assert!(judge.is_finished());
 }
@@ -0,0 +1,117 @@
+use crate::{dsu::RollbackDsu, Grid, Point, N};
+
+// ジャッジが Break と答えたマスのつながり。どのマスが水源とつながっているかを Dsu で持つ
+#[derive(Clone)]
+pub struct Network {
+    house: Vec<Point>,
+    is_water: Grid<bool>,
+    broken: Grid<bool>,
+    cells: Vec<Point>,
+    dsu: RollbackDsu,
+}
+
+impl Network {
+    pub fn new(water: &[Point], house: &[Point]) -> Network {
+        let mut is_water = Grid::new(false);
+        for &w in water {
+            is_water[w] = true;
+        }
+        Network {
+            house: house.to_vec(),
+            is_water,
+            broken: Grid::new(false),
+            cells: Vec::new(),
+            dsu: RollbackDsu::new(N * N),
+        }
+    }
+
+    // p が壊れたことを記録し、隣の壊れたマスとつなぐ
+    pub fn add(&mut self, p: Point) {
+        if self.broken[p] {
+            return;
+        }
+        self.broken[p] = true;
+        self.cells.push(p);
+        if self.is_water[p] {
+            self.dsu.mark_water(p.index());
+        }
+        for n in p.neighbors() {
+            if self.broken[n] {
+                self.dsu.merge(p.index(), n.index());
+            }
+        }
+    }
+
+    // 今の状態。rollback に渡すとここまで戻せる
+    pub fn snapshot(&self) -> (usize, usize) {
+        (self.cells.len(), self.dsu.snapshot())
+    }
+
+    // snapshot の後に add したマスを取り消す
+    pub fn rollback(&mut self, (cells, dsu): (usize, usize)) {
+        for p in self.cells.drain(cells..) {
+            self.broken[p] = false;
+        }
+        self.dsu.rollback(dsu);
+    }
+
+    pub fn is_broken(&self, p: Point) -> bool {
+        self.broken[p]
+    }
+
+    pub fn cells(&self) -> &[Point] {
+        &self.cells
+    }
+
+    // p が壊れていて、どれかの水源につながっているか
+    pub fn is_watered_cell(&self, p: Point) -> bool {
+        self.broken[p] && self.dsu.has_water(p.index())
+    }
+
+    pub fn is_watered(&self, k: usize) -> bool {
+        self.is_watered_cell(self.house[k])
+    }
+
+    pub fn is_complete(&self) -> bool {
+        (0..self.house.len()).all(|k| self.is_watered(k))
+    }
+
+    // どれかの水源につながっている壊れたマス
+    pub fn watered_cells(&self) -> Vec<Point> {
+        self.cells
+            .iter()
+            .copied()
+            .filter(|&p| self.is_watered_cell(p))
+            .collect()
+    }
+}
+
+#[test]
+fn test_network() {
+    // 水源 (0, 0) から (5, 0) まで一本道、(3, 0) から (3, 2) に枝
+    let water = vec![Point::new(0, 0)];
+    let house = vec![Point::new(5, 0), Point::new(3, 2), Point::new(9, 9)];
+    let mut network = Network::new(&water, &house);
+    for x in 1..=5 {
+        network.add(Point::new(x, 0));
+    }
+    network.add(Point::new(3, 1));
+    network.add(Point::new(3, 2));
+    assert!(!network.is_watered(0));
+    network.add(Point::new(0, 0));
+    assert!(network.is_watered(0));
+    assert!(network.is_watered(1));
+    assert!(!network.is_watered(2));
+    assert!(!network.is_complete());
+    assert_eq!(network.watered_cells().len(), 8);
+
+    // snapshot の後に足したマスとつながりを取り消す
+    let snapshot = network.snapshot();
+    network.add(Point::new(9, 9));
+    network.add(Point::new(6, 0));
+    assert!(network.is_watered_cell(Point::new(6, 0)));
+    network.rollback(snapshot);
+    assert!(!network.is_broken(Point::new(9, 9)));
+    assert!(!network.is_watered_cell(Point::new(6, 0)));
+    assert!(network.is_watered(0));
+}
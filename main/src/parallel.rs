@@ -0,0 +1,78 @@
+// parallel feature のときだけスレッドに分けて実行する。
+// どちらでも結果は items の順に並ぶので、乱数の種や家の順番を先に全部決めてから渡せば、
+// スレッド数によらず逐次実行と同じ結果になる
+
+#[cfg(feature = "parallel")]
+pub fn threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+#[cfg(not(feature = "parallel"))]
+pub fn threads() -> usize {
+    1
+}
+
+#[cfg(feature = "parallel")]
+pub fn map<T, U, F>(items: &[T], f: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&T) -> U + Sync,
+{
+    map_with(threads(), items, f)
+}
+
+// threads 本までのスレッドに分ける。1 以下なら逐次実行
+#[cfg(feature = "parallel")]
+pub fn map_with<T, U, F>(threads: usize, items: &[T], f: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&T) -> U + Sync,
+{
+    let threads = threads.min(items.len());
+    if threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let chunk = (items.len() + threads - 1) / threads;
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles = items
+            .chunks(chunk)
+            .map(|c| scope.spawn(move || c.iter().map(f).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    })
+}
+
+#[cfg(not(feature = "parallel"))]
+pub fn map<T, U, F>(items: &[T], f: F) -> Vec<U>
+where
+    F: Fn(&T) -> U,
+{
+    items.iter().map(f).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+pub fn map_with<T, U, F>(_threads: usize, items: &[T], f: F) -> Vec<U>
+where
+    F: Fn(&T) -> U,
+{
+    map(items, f)
+}
+
+#[test]
+fn test_map_keeps_order() {
+    let items = (0..100).collect::<Vec<u64>>();
+    assert_eq!(
+        map(&items, |&x| x * x),
+        items.iter().map(|&x| x * x).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        map_with(3, &items, |&x| x + 1),
+        map_with(1, &items, |&x| x + 1)
+    );
+}
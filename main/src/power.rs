@@ -0,0 +1,200 @@
+use crate::SetMinMax;
+
+const MIN_H: u32 = 10;
+const MAX_H: u32 = 5000;
+// 事前分布の刻み
+const PRIOR_STEP: u32 = 10;
+
+// 累積パワー s で壊れていないマスについて、硬さの重み付き候補 v から
+// 期待コスト最小の打ち方を DP で求め、次に使うパワーと期待コストを返す。
+// s より硬い候補がなければ None
+//
+// 硬さがちょうど候補の値になるように打つのが最適なので、状態は候補の値だけでよい。
+// tail[j] を v[j] より硬い候補の重みの和として
+// f[j] = min_{k > j} tail[j] * (c + v[k] - v[j]) + f[k]
+pub fn best_power(v: &[(u32, f64)], c: u32, s: u32) -> Option<(u32, f64)> {
+    let (values, weights) = states(v, s)?;
+    let (f, next) = solve(&values, &weights, c);
+    let total = weights.iter().sum::<f64>();
+    Some((values[next[0]] - s, f[0] / total))
+}
+
+// best_power の打ち方で打ち続けたときに、各候補の硬さで壊れるまでにかかるコストと重み。
+// 次に使うパワーと一緒に返す
+pub fn schedule_costs(v: &[(u32, f64)], c: u32, s: u32) -> Option<(u32, Vec<(f64, f64)>)> {
+    let (values, weights) = states(v, s)?;
+    let (_, next) = solve(&values, &weights, c);
+    let mut costs = Vec::with_capacity(values.len() - 1);
+    let mut j = 0;
+    let mut sum = 0.0;
+    while j + 1 < values.len() {
+        let k = next[j];
+        sum += (c + values[k] - values[j]) as f64;
+        // j より硬く k 以下の候補は k を狙った一打で壊れる
+        costs.extend(weights[j + 1..=k].iter().map(|&w| (sum, w)));
+        j = k;
+    }
+    Some((values[next[0]] - s, costs))
+}
+
+// s と s より硬い候補の値を昇順に並べ、同じ値の重みをまとめる。先頭の s の重みは 0
+fn states(v: &[(u32, f64)], s: u32) -> Option<(Vec<u32>, Vec<f64>)> {
+    let mut v = v
+        .iter()
+        .filter(|&&(h, w)| s < h && w > 0.0)
+        .copied()
+        .collect::<Vec<_>>();
+    if v.is_empty() {
+        return None;
+    }
+    v.sort_by_key(|&(h, _)| h);
+    let mut values = vec![s];
+    let mut weights = vec![0.0];
+    for (h, w) in v {
+        if *values.last().unwrap() == h {
+            *weights.last_mut().unwrap() += w;
+        } else {
+            values.push(h);
+            weights.push(w);
+        }
+    }
+    Some((values, weights))
+}
+
+// 状態 j から次に狙う状態と f を後ろから埋める
+fn solve(values: &[u32], weights: &[f64], c: u32) -> (Vec<f64>, Vec<usize>) {
+    let m = values.len();
+    let mut tail = vec![0.0; m];
+    for j in (0..m - 1).rev() {
+        tail[j] = tail[j + 1] + weights[j + 1];
+    }
+    let mut f = vec![0.0; m];
+    let mut next = vec![m - 1; m];
+    for j in (0..m - 1).rev() {
+        let mut best = std::f64::MAX;
+        for k in j + 1..m {
+            let cost = tail[j] * (c + values[k] - values[j]) as f64 + f[k];
+            if best.setmin(cost) {
+                next[j] = k;
+            }
+        }
+        f[j] = best;
+    }
+    (f, next)
+}
+
+// 打ち方の表。累積パワー values[j] で壊れていなければ values[next[j]] まで打つ。
+// cost[j] はそこから壊れるまでの期待コスト
+pub struct PowerTable {
+    values: Vec<u32>,
+    next: Vec<usize>,
+    cost: Vec<f64>,
+}
+
+impl PowerTable {
+    // 手がかりがないときに使う、一様な事前分布に対する表
+    pub fn new(c: u32) -> PowerTable {
+        let mut values = vec![0];
+        values.extend((MIN_H..=MAX_H).step_by(PRIOR_STEP as usize));
+        let mut weights = vec![1.0; values.len()];
+        weights[0] = 0.0;
+        PowerTable::from_states(values, weights, c)
+    }
+
+    // 累積パワー s で壊れていないマスの、硬さの重み付き候補 v に対する表。
+    // 表のとおりに打って壊れなかった後の状態も、同じ候補を s より硬い側で条件付けたものになるので、
+    // 候補が変わらない限りそのまま使える。s より硬い候補がなければ None
+    pub fn from_candidates(v: &[(u32, f64)], c: u32, s: u32) -> Option<PowerTable> {
+        let (values, weights) = states(v, s)?;
+        Some(PowerTable::from_states(values, weights, c))
+    }
+
+    fn from_states(values: Vec<u32>, weights: Vec<f64>, c: u32) -> PowerTable {
+        let (f, next) = solve(&values, &weights, c);
+        let mut tail = 0.0;
+        let mut cost = vec![0.0; values.len()];
+        for j in (0..values.len() - 1).rev() {
+            tail += weights[j + 1];
+            cost[j] = f[j] / tail;
+        }
+        PowerTable { values, next, cost }
+    }
+
+    // s がちょうど表の状態で、まだ打つ先が残っているか
+    pub fn covers(&self, s: u32) -> bool {
+        match self.values.binary_search(&s) {
+            Ok(j) => j + 1 < self.values.len(),
+            Err(_) => false,
+        }
+    }
+
+    // 累積パワー s で壊れていないときの (パワー, 期待コスト)
+    pub fn get(&self, s: u32) -> (u32, u32) {
+        // s 以下で最大の状態から打つとみなす
+        let j = match self.values.binary_search(&s) {
+            Ok(j) => j,
+            Err(j) => j - 1,
+        };
+        if j + 1 == self.values.len() {
+            return (MIN_H, MIN_H);
+        }
+        let power = self.values[self.next[j]] - s;
+        (power, self.cost[j].round() as u32)
+    }
+}
+
+// テスト用に、c と累積ダメージ s と、s より硬い (高さ, 重み) の組を乱数で作る
+#[cfg(test)]
+pub(crate) fn random_case<R: rand::Rng>(rng: &mut R) -> (u32, u32, Vec<(u32, f64)>) {
+    use rand::seq::SliceRandom;
+
+    let c = *[1, 2, 4, 8, 16, 32, 64, 128].choose(rng).unwrap();
+    let s = rng.gen_range(0..200);
+    let v = (0..rng.gen_range(1..20))
+        .map(|_| (rng.gen_range(s + 1..=MAX_H), rng.gen_range(0.1..1.0)))
+        .collect();
+    (c, s, v)
+}
+
+#[test]
+fn test_power_table() {
+    use rand_pcg::Mcg128Xsl64;
+
+    // 表をたどって打つと、各状態で DP を解き直したときと同じパワーとコストになる
+    let mut rng = Mcg128Xsl64::new(2);
+    for _ in 0..100 {
+        let (c, mut s, v) = random_case(&mut rng);
+        let table = PowerTable::from_candidates(&v, c, s).unwrap();
+        while table.covers(s) {
+            let (power, cost) = table.get(s);
+            let (expected, expected_cost) = best_power(&v, c, s).unwrap();
+            assert_eq!(power, expected);
+            assert_eq!(cost, expected_cost.round() as u32);
+            s += power;
+        }
+        assert!(best_power(&v, c, s).is_none());
+    }
+}
+
+#[test]
+fn test_best_power() {
+    use rand_pcg::Mcg128Xsl64;
+
+    // 決まったパワーで打ち続けるどの打ち方よりも悪くならない
+    let mut rng = Mcg128Xsl64::new(1);
+    for _ in 0..100 {
+        let (c, s, v) = random_case(&mut rng);
+        let (power, cost) = best_power(&v, c, s).unwrap();
+        assert!(0 < power && power <= MAX_H);
+        let tail = v.iter().filter(|&&(h, _)| s < h).collect::<Vec<_>>();
+        let sum = tail.iter().map(|&&(_, w)| w).sum::<f64>();
+        for q in 1..=MAX_H {
+            let fixed = tail
+                .iter()
+                .map(|&&(h, w)| ((c + q) * ((h - s + q - 1) / q)) as f64 * w)
+                .sum::<f64>()
+                / sum;
+            assert!(cost <= fixed * (1.0 + 1e-9));
+        }
+    }
+}
@@ -1,9 +1,22 @@
 use noise::Perlin;
 use rand::Rng;
+use std::time::{Duration, Instant};
 
-use crate::N;
+use crate::{Grid, Point, N};
 
-struct LandGen {
+// y_offset1, x_offset1, y_offset2, x_offset2, freq1, freq2, power の事前分布の範囲
+pub const PARAM_RANGE: [(f64, f64); 7] = [
+    (0.0, 1.0),
+    (0.0, 1.0),
+    (0.0, 1.0),
+    (0.0, 1.0),
+    (2.0, 8.0),
+    (10.0, 20.0),
+    (2.0, 4.0),
+];
+
+pub struct LandGen {
+    seed: u32,
     perlin: Perlin,
     y_offset1: f64,
     x_offset1: f64,
@@ -15,20 +28,52 @@ struct LandGen {
 }
 
 impl LandGen {
-    fn new<R: Rng>(rng: &mut R) -> LandGen {
+    pub fn new<R: Rng>(rng: &mut R) -> LandGen {
+        let seed = rng.gen();
+        let params = [
+            rng.gen::<f64>(),
+            rng.gen::<f64>(),
+            rng.gen::<f64>(),
+            rng.gen::<f64>(),
+            rng.gen_range(2.0..8.0),
+            rng.gen_range(10.0..20.0),
+            rng.gen_range(2.0..4.0),
+        ];
+        LandGen::with_params(seed, params)
+    }
+
+    pub fn with_params(seed: u32, params: [f64; 7]) -> LandGen {
         LandGen {
-            perlin: Perlin::new(rng.gen()),
-            y_offset1: rng.gen::<f64>(),
-            x_offset1: rng.gen::<f64>(),
-            y_offset2: rng.gen::<f64>(),
-            x_offset2: rng.gen::<f64>(),
-            freq1: rng.gen_range(2.0..8.0),
-            freq2: rng.gen_range(10.0..20.0),
-            power: rng.gen_range(2.0..4.0),
+            seed,
+            perlin: Perlin::new(seed),
+            y_offset1: params[0],
+            x_offset1: params[1],
+            y_offset2: params[2],
+            x_offset2: params[3],
+            freq1: params[4],
+            freq2: params[5],
+            power: params[6],
         }
     }
 
-    fn get(&self, r: usize, c: usize) -> f64 {
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn params(&self) -> [f64; 7] {
+        [
+            self.y_offset1,
+            self.x_offset1,
+            self.y_offset2,
+            self.x_offset2,
+            self.freq1,
+            self.freq2,
+            self.power,
+        ]
+    }
+
+    // 値域を [10, 5000] に伸ばす前の高さ
+    pub fn get(&self, r: usize, c: usize) -> f64 {
         let y1 = self.y_offset1 + (r as f64 / N as f64) * self.freq1;
         let x1 = self.x_offset1 + (c as f64 / N as f64) * self.freq1;
         let y2 = self.y_offset2 + (r as f64 / N as f64) * self.freq2;
@@ -37,32 +82,209 @@ impl LandGen {
         let h = 1.0 / (1.0 + (-3.0 * (h - 0.25)).exp());
         h.powf(self.power)
     }
-}
 
-pub fn gen_h<R: Rng>(rng: &mut R, landmark: &[(usize, usize)], th: f64) -> Vec<u32> {
-    let th = 0.8 / 5000.0 * th;
-    loop {
-        let get = LandGen::new(rng);
-        if landmark.iter().any(|&(r, c)| get.get(r, c) > th) {
-            continue;
-        }
-        let mut h = Vec::with_capacity(N * N);
+    pub fn to_grid(&self) -> Grid<u32> {
+        let mut h = Grid::new(0.0);
         let mut min = std::f64::MAX;
         let mut max = std::f64::MIN;
         for r in 0..N {
             for c in 0..N {
-                let v = get.get(r, c);
+                let v = self.get(r, c);
                 min = min.min(v);
                 max = max.max(v);
-                h.push(v);
+                h[Point::new(r as u32, c as u32)] = v;
             }
         }
-        break h
-            .into_iter()
-            .map(|h| {
-                let h = (10.0 + (h - min) / (max - min) * (5000.0 - 10.0)).round();
-                h as u32
-            })
-            .collect();
+        h.map(|h| (10.0 + (h - min) / (max - min) * (5000.0 - 10.0)).round() as u32)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SampleStats {
+    // 作った LandGen の数
+    pub attempts: usize,
+    // しきい値を満たして受け入れた数
+    pub accepted: usize,
+    // 上限に達して、一番はみ出しの小さいもので妥協した数
+    pub fallbacks: usize,
+    // fallbacks のうち、試行回数ではなく期限で打ち切った数
+    pub timeouts: usize,
+    pub elapsed: Duration,
+}
+
+impl SampleStats {
+    pub fn merge(&mut self, other: &SampleStats) {
+        self.attempts += other.attempts;
+        self.accepted += other.accepted;
+        self.fallbacks += other.fallbacks;
+        self.timeouts += other.timeouts;
+        self.elapsed += other.elapsed;
+    }
+
+    pub fn acceptance_rate(&self) -> f64 {
+        if self.attempts == 0 {
+            0.0
+        } else {
+            self.accepted as f64 / self.attempts as f64
+        }
+    }
+
+    // 高さマップ 1 つを作るのにかかった平均時間
+    pub fn time_per_sample(&self) -> Option<Duration> {
+        let n = self.accepted + self.fallbacks;
+        if n == 0 {
+            None
+        } else {
+            Some(self.elapsed / n as u32)
+        }
+    }
+}
+
+// landmark の高さがしきい値以下になる高さマップを棄却法で作る。
+// 試行回数か期限を過ぎたら、しきい値からのはみ出しが一番小さかったものを返す
+pub struct Sampler<'a> {
+    landmark: &'a [(usize, usize)],
+    th: f64,
+    max_attempts: Option<usize>,
+    deadline: Option<Instant>,
+    stats: SampleStats,
+}
+
+impl<'a> Sampler<'a> {
+    pub fn new(landmark: &'a [(usize, usize)], th: f64) -> Sampler<'a> {
+        Sampler {
+            landmark,
+            th: 0.8 / 5000.0 * th,
+            max_attempts: None,
+            deadline: None,
+            stats: SampleStats::default(),
+        }
+    }
+
+    // 1 つの高さマップあたりの試行回数の上限
+    pub fn set_max_attempts(&mut self, max_attempts: Option<usize>) {
+        self.max_attempts = max_attempts;
     }
+
+    // 非常用の打ち切り。普通は試行回数の上限が先に効き、高さマップは乱数の種だけで決まる。
+    // 期限に達したときだけ結果が CPU の速さで変わるので、その回数は timeouts に数える
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn stats(&self) -> SampleStats {
+        self.stats
+    }
+
+    fn violation(&self, get: &LandGen) -> f64 {
+        self.landmark
+            .iter()
+            .map(|&(r, c)| get.get(r, c) - self.th)
+            .fold(0.0, f64::max)
+    }
+
+    pub fn sample<R: Rng>(&mut self, rng: &mut R) -> Grid<u32> {
+        let start = Instant::now();
+        let mut best: Option<(f64, LandGen)> = None;
+        for attempt in 1.. {
+            self.stats.attempts += 1;
+            let get = LandGen::new(rng);
+            let v = self.violation(&get);
+            if v == 0.0 {
+                self.stats.accepted += 1;
+                best = Some((v, get));
+                break;
+            }
+            if match &best {
+                Some(b) => v < b.0,
+                None => true,
+            } {
+                best = Some((v, get));
+            }
+            let over_attempts = matches!(self.max_attempts, Some(m) if m <= attempt);
+            // 時刻を見るのは重いので間引く
+            let over_time =
+                attempt % 64 == 0 && matches!(self.deadline, Some(d) if d <= Instant::now());
+            if over_attempts || over_time {
+                self.stats.fallbacks += 1;
+                if !over_attempts {
+                    self.stats.timeouts += 1;
+                }
+                break;
+            }
+        }
+        let h = best.unwrap().1.to_grid();
+        self.stats.elapsed += start.elapsed();
+        h
+    }
+}
+
+pub fn gen_h<R: Rng>(rng: &mut R, landmark: &[(usize, usize)], th: f64) -> Grid<u32> {
+    Sampler::new(landmark, th).sample(rng)
+}
+
+// 水源と家は 1/h に比例する確率で置かれるので、その配置が出る対数尤度
+pub fn log_likelihood(h: &Grid<u32>, landmark: &[(usize, usize)]) -> f64 {
+    let mut z = 0.0;
+    for r in 0..N as u32 {
+        for c in 0..N as u32 {
+            z += 1.0 / h[Point::new(r, c)] as f64;
+        }
+    }
+    landmark
+        .iter()
+        .map(|&(r, c)| -(h[Point::new(r as u32, c as u32)] as f64).ln() - z.ln())
+        .sum()
+}
+
+// 各高さマップを landmark の配置の尤度で重み付けする。重みは平均が 1 になるようにする。
+// temperature が 1 より大きいと重みの偏りを和らげる
+pub fn likelihood_weights(
+    h: &[Grid<u32>],
+    landmark: &[(usize, usize)],
+    temperature: f64,
+) -> Vec<f64> {
+    let l = h
+        .iter()
+        .map(|h| log_likelihood(h, landmark) / temperature)
+        .collect::<Vec<_>>();
+    let max = l.iter().copied().fold(std::f64::MIN, f64::max);
+    let sum = l.iter().map(|&l| (l - max).exp()).sum::<f64>();
+    l.into_iter()
+        .map(|l| (l - max).exp() / sum * h.len() as f64)
+        .collect()
+}
+
+// 重みに比例して n 個を選び直す (系統抽出)
+pub fn resample<R: Rng>(rng: &mut R, samples: Vec<(Grid<u32>, f64)>, n: usize) -> Vec<Grid<u32>> {
+    let sum = samples.iter().map(|&(_, w)| w).sum::<f64>();
+    let step = sum / n as f64;
+    let mut u = rng.gen::<f64>() * step;
+    let mut acc = 0.0;
+    let mut picked = Vec::with_capacity(n);
+    for (h, w) in samples {
+        acc += w;
+        while u < acc && picked.len() < n {
+            picked.push(h.clone());
+            u += step;
+        }
+    }
+    picked
+}
+
+#[test]
+fn test_to_grid() {
+    use rand_pcg::Mcg128Xsl64;
+
+    // get(r, c) の値は Point::new(r, c) に入る。一番高い点と低い点で確かめる
+    let gen = LandGen::new(&mut Mcg128Xsl64::new(1));
+    let h = gen.to_grid();
+    let cells = (0..N).flat_map(|r| (0..N).map(move |c| (r, c)));
+    let cmp = |a: &(usize, usize), b: &(usize, usize)| {
+        gen.get(a.0, a.1).partial_cmp(&gen.get(b.0, b.1)).unwrap()
+    };
+    let (r, c) = cells.clone().max_by(cmp).unwrap();
+    assert_eq!(h[Point::new(r as u32, c as u32)], 5000);
+    let (r, c) = cells.min_by(cmp).unwrap();
+    assert_eq!(h[Point::new(r as u32, c as u32)], 10);
 }
@@ -0,0 +1,107 @@
+use crate::config::RiskMeasure;
+
+// 1 マスを壊すまでのコストの分布を、経路探索で使う 1 つの値にまとめる方法
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Risk {
+    // 期待値
+    Mean,
+    // 上側の分位点。引数は下からの割合
+    Quantile(f64),
+    // 上側 1 - alpha の平均 (CVaR)
+    Cvar(f64),
+    // 期待値 + lambda * 標準偏差
+    MeanStd(f64),
+}
+
+impl Risk {
+    pub fn new(measure: RiskMeasure, level: f64, penalty: f64) -> Risk {
+        let level = level.max(0.0).min(1.0);
+        match measure {
+            RiskMeasure::Mean => Risk::Mean,
+            RiskMeasure::Quantile => Risk::Quantile(level),
+            RiskMeasure::Cvar => Risk::Cvar(level),
+            RiskMeasure::Variance => Risk::MeanStd(penalty),
+        }
+    }
+
+    // costs は (コスト, 重み) の組。空なら 0
+    pub fn measure(&self, costs: &[(f64, f64)]) -> f64 {
+        let total = costs.iter().map(|&(_, w)| w).sum::<f64>();
+        if costs.is_empty() || total <= 0.0 {
+            return 0.0;
+        }
+        let mean = costs.iter().map(|&(x, w)| x * w).sum::<f64>() / total;
+        match *self {
+            Risk::Mean => mean,
+            Risk::MeanStd(lambda) => {
+                let var = costs
+                    .iter()
+                    .map(|&(x, w)| (x - mean).powi(2) * w)
+                    .sum::<f64>()
+                    / total;
+                mean + lambda * var.sqrt()
+            }
+            Risk::Quantile(alpha) => {
+                let sorted = sorted(costs);
+                let mut acc = 0.0;
+                for &(x, w) in sorted.iter() {
+                    acc += w;
+                    if alpha * total <= acc {
+                        return x;
+                    }
+                }
+                sorted.last().unwrap().0
+            }
+            Risk::Cvar(alpha) => {
+                let sorted = sorted(costs);
+                let mut rest = (1.0 - alpha) * total;
+                if rest <= 0.0 {
+                    return sorted.last().unwrap().0;
+                }
+                let tail = rest;
+                let mut sum = 0.0;
+                // 高い方から tail の重みの分だけ平均する
+                for &(x, w) in sorted.iter().rev() {
+                    let w = w.min(rest);
+                    sum += x * w;
+                    rest -= w;
+                    if rest <= 0.0 {
+                        break;
+                    }
+                }
+                sum / tail
+            }
+        }
+    }
+}
+
+fn sorted(costs: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let mut sorted = costs.to_vec();
+    sorted.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    sorted
+}
+
+#[test]
+fn test_risk() {
+    use crate::power::{best_power, random_case, schedule_costs};
+    use rand_pcg::Mcg128Xsl64;
+
+    let mut rng = Mcg128Xsl64::new(1);
+    for _ in 0..100 {
+        let (c, s, v) = random_case(&mut rng);
+        let (power, expected) = best_power(&v, c, s).unwrap();
+        let (p, costs) = schedule_costs(&v, c, s).unwrap();
+        assert_eq!(power, p);
+        // 期待値は DP の値と一致し、他の尺度は期待値より悲観的になる
+        let mean = Risk::Mean.measure(&costs);
+        assert!((mean - expected).abs() <= expected * 1e-9);
+        let max = costs.iter().map(|&(x, _)| x).fold(0.0, f64::max);
+        let quantile = Risk::Quantile(0.8).measure(&costs);
+        let cvar = Risk::Cvar(0.8).measure(&costs);
+        assert!(quantile <= cvar * (1.0 + 1e-9));
+        assert!(mean <= cvar * (1.0 + 1e-9));
+        assert!(cvar <= max * (1.0 + 1e-9));
+        assert!(mean <= Risk::MeanStd(0.5).measure(&costs));
+        assert_eq!(Risk::Cvar(1.0).measure(&costs), max);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::{Grid, Point};
+
+// 取り出すキーが単調に増え、入っているキーの幅が window 未満のときだけ使える優先度付きキュー (Dial)。
+// キーを window で割った余りのバケットに入れる
+pub struct BucketQueue<T> {
+    current: u64,
+    len: usize,
+    mask: u64,
+    buckets: Vec<Vec<T>>,
+}
+
+impl<T> BucketQueue<T> {
+    pub fn new() -> BucketQueue<T> {
+        BucketQueue {
+            current: 0,
+            len: 0,
+            mask: 0,
+            buckets: vec![Vec::new()],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 空にして、最初のキーを start、キーの幅を window 未満にする
+    pub fn reset(&mut self, start: u64, window: u64) {
+        let size = window.next_power_of_two().max(1) as usize;
+        if self.buckets.len() < size {
+            self.buckets.resize_with(size, Vec::new);
+        }
+        for b in self.buckets.iter_mut() {
+            b.clear();
+        }
+        self.mask = self.buckets.len() as u64 - 1;
+        self.current = start;
+        self.len = 0;
+    }
+
+    // key は最後に取り出したキー以上で、それに window を足したものより小さくなければならない
+    pub fn push(&mut self, key: u64, value: T) {
+        debug_assert!(self.current <= key && key - self.current <= self.mask);
+        self.buckets[(key & self.mask) as usize].push(value);
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<(u64, T)> {
+        if self.len == 0 {
+            return None;
+        }
+        loop {
+            if let Some(v) = self.buckets[(self.current & self.mask) as usize].pop() {
+                self.len -= 1;
+                return Some((self.current, v));
+            }
+            self.current += 1;
+        }
+    }
+}
+
+impl<T> Default for BucketQueue<T> {
+    fn default() -> BucketQueue<T> {
+        BucketQueue::new()
+    }
+}
+
+// 隣に進むたびに進んだ先のマスのコストを足す最短路。
+// バッファは使い回し、呼び出しごとに世代を進めて前回の値を無効にする
+pub struct DistanceField {
+    queue: BucketQueue<Point>,
+    sources: Vec<(Point, u64)>,
+    dist: Grid<u64>,
+    prev: Grid<Option<Point>>,
+    stamp: Grid<u32>,
+    done: Grid<u32>,
+    generation: u32,
+}
+
+impl DistanceField {
+    pub fn new() -> DistanceField {
+        DistanceField {
+            queue: BucketQueue::new(),
+            sources: Vec::new(),
+            dist: Grid::new(std::u64::MAX),
+            prev: Grid::new(None),
+            stamp: Grid::new(0),
+            done: Grid::new(0),
+            generation: 0,
+        }
+    }
+
+    // sources の (マス, 初期距離) から始めて target を確定するまで広げる。target が None なら全マス。
+    // cost の値は min_cost 以上 max_cost 以下でなければならない。
+    // target があれば min_cost * マンハッタン距離 を下界にした A* になる。min_cost が 0 なら普通のダイクストラ
+    pub fn run(
+        &mut self,
+        cost: &Grid<u32>,
+        (min_cost, max_cost): (u32, u32),
+        sources: impl Iterator<Item = (Point, u64)>,
+        target: Option<Point>,
+    ) {
+        self.search(cost, (min_cost, max_cost), sources, target, |p| {
+            Some(p) == target
+        });
+    }
+
+    // sources から広げて、is_target なマスのうち最初に確定したものを返す。
+    // cost が std::u32::MAX のマスは通らず、それ以外の値は max_cost 以下でなければならない
+    pub fn nearest(
+        &mut self,
+        cost: &Grid<u32>,
+        max_cost: u32,
+        sources: impl Iterator<Item = (Point, u64)>,
+        is_target: impl Fn(Point) -> bool,
+    ) -> Option<Point> {
+        self.search(cost, (0, max_cost), sources, None, is_target)
+    }
+
+    fn search(
+        &mut self,
+        cost: &Grid<u32>,
+        (min_cost, max_cost): (u32, u32),
+        sources: impl Iterator<Item = (Point, u64)>,
+        target: Option<Point>,
+        is_target: impl Fn(Point) -> bool,
+    ) -> Option<Point> {
+        self.generation += 1;
+        let bound = |p: Point| match target {
+            Some(t) => min_cost as u64 * p.manhattan(&t) as u64,
+            None => 0,
+        };
+        self.sources.clear();
+        self.sources.extend(sources.map(|(p, w)| (p, w + bound(p))));
+        // 1 歩で増えるキーは進んだ先のコストと下界の差の和以下なので、それと始点のキーの幅が入ればよい
+        let start = self.sources.iter().map(|&(_, k)| k).min().unwrap_or(0);
+        let end = self.sources.iter().map(|&(_, k)| k).max().unwrap_or(0);
+        let step = max_cost as u64 + min_cost as u64;
+        self.queue.reset(start, (end - start).max(step) + 1);
+        for i in 0..self.sources.len() {
+            let (p, key) = self.sources[i];
+            if self.set(p, key - bound(p), None) {
+                self.queue.push(key, p);
+            }
+        }
+        while let Some((_, p)) = self.queue.pop() {
+            if self.done[p] == self.generation {
+                continue;
+            }
+            self.done[p] = self.generation;
+            if is_target(p) {
+                return Some(p);
+            }
+            let d = self.dist[p];
+            for n in p.neighbors() {
+                if cost[n] == std::u32::MAX {
+                    continue;
+                }
+                let w = d + cost[n] as u64;
+                if self.done[n] != self.generation && self.set(n, w, Some(p)) {
+                    self.queue.push(w + bound(n), n);
+                }
+            }
+        }
+        None
+    }
+
+    fn set(&mut self, p: Point, w: u64, prev: Option<Point>) -> bool {
+        if self.stamp[p] == self.generation && self.dist[p] <= w {
+            return false;
+        }
+        self.stamp[p] = self.generation;
+        self.dist[p] = w;
+        self.prev[p] = prev;
+        true
+    }
+
+    // 直前の run で届かなかったマスは None
+    pub fn dist(&self, p: Point) -> Option<u64> {
+        if self.stamp[p] == self.generation {
+            Some(self.dist[p])
+        } else {
+            None
+        }
+    }
+
+    // 始点から p までの道。始点側が先頭で、p を含む
+    pub fn path(&self, p: Point) -> Vec<Point> {
+        let mut path = vec![p];
+        let mut cur = p;
+        while let Some(nex) = self.prev[cur] {
+            path.push(nex);
+            cur = nex;
+        }
+        path.reverse();
+        path
+    }
+}
+
+impl Default for DistanceField {
+    fn default() -> DistanceField {
+        DistanceField::new()
+    }
+}
+
+#[test]
+fn test_distance_field() {
+    use crate::N;
+    use rand::prelude::*;
+    use rand_pcg::Mcg128Xsl64;
+
+    // A* でも target までの距離はダイクストラと同じで、道のコストの和とも一致する
+    let mut rng = Mcg128Xsl64::new(1);
+    let mut cost = Grid::new(0);
+    for x in 0..N as u32 {
+        for y in 0..N as u32 {
+            cost[Point::new(x, y)] = rng.gen_range(20..5000);
+        }
+    }
+    let mut field = DistanceField::new();
+    for _ in 0..10 {
+        let mut point = || Point::new(rng.gen_range(0..N as u32), rng.gen_range(0..N as u32));
+        let sources = [(point(), 0), (point(), 100)];
+        let target = point();
+        field.run(&cost, (0, 5000), sources.iter().copied(), None);
+        let expected = field.dist(target).unwrap();
+        field.run(&cost, (20, 5000), sources.iter().copied(), Some(target));
+        assert_eq!(field.dist(target), Some(expected));
+        let path = field.path(target);
+        let start = sources.iter().find(|s| s.0 == path[0]).unwrap().1;
+        let sum = path[1..].iter().map(|&p| cost[p] as u64).sum::<u64>();
+        assert_eq!(start + sum, expected);
+    }
+
+    // std::u32::MAX のマスは通らず、目印のうち一番近いものまでで止まる
+    let mut wall = Grid::new(1);
+    for y in 0..N as u32 {
+        wall[Point::new(5, y)] = std::u32::MAX;
+    }
+    let start = std::iter::once((Point::new(0, 0), 0));
+    let found = field.nearest(&wall, 1, start, |p| p.x() == 3 || p.x() == 9);
+    assert_eq!(found, Some(Point::new(3, 0)));
+    assert_eq!(field.dist(Point::new(3, 0)), Some(3));
+    assert_eq!(field.dist(Point::new(9, 0)), None);
+}
@@ -1,20 +1,61 @@
-use crate::{predict_h::gen_h, Grid, Point, SetMinMax, N};
-use rand::seq::SliceRandom;
+use crate::{
+    anneal::{anneal, AnnealConfig},
+    config::{ConnectOrder, SolverConfig, Weighting},
+    ensemble::{Ensemble, OBS_RADIUS},
+    estimate::{Estimator, Method, Sample},
+    infer::{infer, InferConfig},
+    network::Network,
+    parallel,
+    power::{best_power, schedule_costs, PowerTable},
+    predict_h::{likelihood_weights, resample, SampleStats, Sampler},
+    risk::Risk,
+    shortest_path::DistanceField,
+    steiner::{order_from_water, steiner},
+    time_keeper::{Pace, TimeKeeper},
+    Grid, Point, SetMinMax, N,
+};
+use rand::{seq::SliceRandom, Rng};
 use rand_pcg::Mcg128Xsl64;
-use std::{cmp::Ordering, collections::BinaryHeap};
+use std::{cmp::Ordering, time::Instant};
 
 pub struct Solver {
-    grid: Grid<bool>,
     water: Vec<Point>,
     house: Vec<Point>,
     c: u32,
-    h: Vec<Grid<u32>>,
+    ensemble: Ensemble,
     guess_cost: Grid<u32>,
+    // guess_cost の最小値と最大値。最小値は A* の下界に使う
+    min_cost: u32,
+    max_cost: u32,
+    astar: bool,
+    order: ConnectOrder,
+    threads: usize,
+    damage: Grid<u32>,
+    // 実際に壊したマスのつながり
+    network: Network,
+    // 前回 update_cost してから観測したマス
+    dirty: Vec<Point>,
+    method: Option<Method>,
+    estimator: Option<Estimator>,
+    // 候補が尽きたときの打ち方
+    power: PowerTable,
+    // いま掘っているマスの打ち方の表。候補が変わるまで使い回す
+    schedule: Option<Schedule>,
+    fallback_power: Option<u32>,
+    // guess_cost をコストの分布からどう決めるか
+    risk: Risk,
+    sample_stats: SampleStats,
 }
 
-struct DState {
-    p: Point,
-    w: u64,
+const ESTIMATE_NEIGHBORS: usize = 12;
+// 推定値の分布を近似する分位点
+const QUANTILES: [f64; 5] = [-1.28, -0.52, 0.0, 0.52, 1.28];
+// 推定の標準偏差がこれくらいだとアンサンブルと半々の重みになる
+const CONFIDENT_SD: f64 = 500.0;
+
+pub(crate) struct DState {
+    pub(crate) p: Point,
+    pub(crate) w: u64,
 }
 
 impl PartialEq for DState {
@@ -27,7 +68,7 @@ impl Eq for DState {}
 
 impl PartialOrd for DState {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(&other))
+        Some(self.cmp(other))
     }
 }
 
@@ -37,31 +78,11 @@ impl Ord for DState {
     }
 }
 
-fn guess_power(h: &[Grid<u32>], p: Point, c: u32, s: u32) -> (u32, u32) {
-    let v = h
-        .iter()
-        .filter_map(|h| if s < h[p] { Some(h[p] - s) } else { None })
-        .collect::<Vec<_>>();
-    if v.is_empty() {
-        return (100, 100 + c);
-    }
-    if v.len() == 1 {
-        return (v[0], v[0] + c);
-    }
-    let mut best = std::u32::MAX;
-    let mut best_power = 100;
-    for q in 10..=5000 {
-        let cost = v
-            .iter()
-            .map(|&d| ((c + q) * ((d + q - 1) / q)))
-            .sum::<u32>();
-        if best.setmin(cost) {
-            best_power = q;
-        } else {
-            return (best_power, best);
-        }
-    }
-    (best_power, best)
+// 掘っているマス p の打ち方の表と、それを作ったときの s より硬い候補
+struct Schedule {
+    p: Point,
+    candidates: Vec<(u32, f64)>,
+    table: PowerTable,
 }
 
 impl Solver {
@@ -70,6 +91,8 @@ impl Solver {
         water: &[(u32, u32)],
         house: &[(u32, u32)],
         c: u32,
+        config: &SolverConfig,
+        time: &TimeKeeper,
     ) -> Solver {
         let mut landmark = Vec::new();
         for &(x, y) in water.iter() {
@@ -78,85 +101,607 @@ impl Solver {
         for &(x, y) in house.iter() {
             landmark.push((x as usize, y as usize));
         }
-        let mut h = Vec::with_capacity(10);
-        for _ in 0..10 {
-            h.push(Grid::from_vec(gen_h(rng, &landmark, 30.0)));
+        let count = match config.weighting {
+            Weighting::Off | Weighting::Importance => config.samples,
+            Weighting::Resample => config.pool,
+        };
+        let deadline = time.at(config.sample_share);
+        let seeds = (0..count).map(|_| rng.gen::<u128>()).collect::<Vec<_>>();
+        let threads = config.threads.unwrap_or_else(parallel::threads);
+        let samples = parallel::map_with(threads, &seeds, |&seed| {
+            let mut sampler = Sampler::new(&landmark, config.threshold);
+            sampler.set_max_attempts(Some(config.max_attempts));
+            sampler.set_deadline(Some(deadline));
+            let h = sampler.sample(&mut Mcg128Xsl64::new(seed));
+            (h, sampler.stats())
+        });
+        let mut sample_stats = SampleStats::default();
+        let mut h = Vec::with_capacity(count);
+        for (grid, stats) in samples {
+            sample_stats.merge(&stats);
+            h.push(grid);
         }
-        let mut guess_cost = Grid::new(0);
+        let (h, weight) = match config.weighting {
+            Weighting::Off => {
+                let weight = vec![1.0; h.len()];
+                (h, weight)
+            }
+            Weighting::Importance => {
+                let weight = likelihood_weights(&h, &landmark, config.temperature);
+                (h, weight)
+            }
+            Weighting::Resample => {
+                let weight = likelihood_weights(&h, &landmark, config.temperature);
+                let h = resample(rng, h.into_iter().zip(weight).collect(), config.samples);
+                let weight = vec![1.0; h.len()];
+                (h, weight)
+            }
+        };
+        let water = water
+            .iter()
+            .map(|&(x, y)| Point::new(x, y))
+            .collect::<Vec<_>>();
+        let house = house
+            .iter()
+            .map(|&(x, y)| Point::new(x, y))
+            .collect::<Vec<_>>();
+        let network = Network::new(&water, &house);
+        let mut solver = Solver {
+            water,
+            house,
+            c,
+            ensemble: Ensemble::with_weights(h, weight),
+            guess_cost: Grid::new(0),
+            min_cost: 0,
+            max_cost: 0,
+            astar: config.astar,
+            order: config.order,
+            threads,
+            damage: Grid::new(0),
+            network,
+            dirty: Vec::new(),
+            method: config.estimate,
+            estimator: None,
+            power: PowerTable::new(c),
+            schedule: None,
+            fallback_power: config.fallback_power,
+            risk: Risk::new(config.risk, config.risk_level, config.risk_penalty),
+            sample_stats,
+        };
+        solver.update_all_cost();
+        solver
+    }
+
+    pub fn samples(&self) -> usize {
+        self.ensemble.samples().len()
+    }
+
+    pub fn sample_stats(&self) -> SampleStats {
+        self.sample_stats
+    }
+
+    fn update_all_cost(&mut self) {
         for x in 0..N as u32 {
             for y in 0..N as u32 {
-                let p = Point::new(x, y);
-                guess_cost[p] = guess_power(&h, p, c, 0).1;
+                self.update_cell_cost(Point::new(x, y));
             }
         }
-        Solver {
-            grid: Grid::new(false),
-            water: water.iter().map(|&(x, y)| Point::new(x, y)).collect(),
-            house: house.iter().map(|&(x, y)| Point::new(x, y)).collect(),
-            c,
-            h,
-            guess_cost,
+        self.update_cost_range();
+    }
+
+    // 壊したマスは 0 なので、一度でも壊すと A* の下界は効かなくなる
+    fn update_cost_range(&mut self) {
+        let mut min = std::u32::MAX;
+        let mut max = 0;
+        for x in 0..N as u32 {
+            for y in 0..N as u32 {
+                let c = self.guess_cost[Point::new(x, y)];
+                min.setmin(c);
+                max.setmax(c);
+            }
         }
+        self.min_cost = min;
+        self.max_cost = max;
     }
 
-    pub fn reset(&mut self) {
-        self.grid = Grid::new(false);
+    // ここまでの観測に合う地形のパラメータを推定し、その高さマップをアンサンブルに加える
+    pub fn infer(&mut self, rng: &mut Mcg128Xsl64, config: &InferConfig, weight: f64) -> usize {
+        let fitted = infer(rng, &self.ensemble.observations(), config);
+        for gen in fitted.iter() {
+            self.ensemble.push(gen.to_grid(), weight);
+        }
+        self.dirty.clear();
+        self.update_all_cost();
+        fitted.len()
     }
 
-    fn dijkstra(&self, start: Point) -> (Point, Grid<Option<Point>>) {
-        let mut heap = BinaryHeap::new();
-        let mut dist = Grid::new(std::u64::MAX);
-        let mut prev = Grid::new(None);
-        dist[start] = 0;
-        heap.push(DState { p: start, w: 0 });
-        while let Some(s) = heap.pop() {
-            if dist[s.p] < s.w {
-                continue;
+    // アンサンブルのサンプルに、補間した推定値の分位点を確からしさに応じた重みで加える
+    fn candidates(&self, p: Point) -> Vec<(u32, f64)> {
+        let mut v = self.ensemble.weighted_values_at(p);
+        if let Some(estimator) = &self.estimator {
+            let (mean, sd) = estimator.estimate_at(p);
+            let confidence = 1.0 / (1.0 + (sd / CONFIDENT_SD).powi(2));
+            let w = self.ensemble.samples().len() as f64 * confidence / QUANTILES.len() as f64;
+            for z in QUANTILES.iter() {
+                let h = (mean + z * sd).max(10.0).min(5000.0).round() as u32;
+                v.push((h, w));
             }
-            for n in s.p.neighbors() {
-                if self.grid[n] {
-                    prev[n] = Some(s.p);
-                    return (n, prev);
-                }
-                let w = s.w + self.guess_cost[n] as u64;
-                if dist[n].setmin(w) {
-                    prev[n] = Some(s.p);
-                    if self.water.iter().any(|p| *p == n) {
-                        return (n, prev);
+        }
+        v
+    }
+
+    fn update_cell_cost(&mut self, p: Point) {
+        self.guess_cost[p] = if self.network.is_broken(p) {
+            0
+        } else {
+            self.risky_cost(p, self.damage[p])
+        };
+    }
+
+    // 観測の影響があるマスだけ guess_cost を計算し直す。
+    // 補間の全体の平均とバリオグラムは観測の数が倍になるたびに当てはめ直し、そのときは全マスを計算し直す
+    pub fn update_cost(&mut self) {
+        if let Some(method) = self.method {
+            let observations = self
+                .ensemble
+                .observations()
+                .into_iter()
+                .map(|(p, i)| Sample::from_interval(p, i, self.ensemble.conditional_mean(p)))
+                .collect::<Vec<_>>();
+            let refit = match &self.estimator {
+                Some(estimator) => 2 * estimator.fitted() <= observations.len(),
+                None => !observations.is_empty(),
+            };
+            if refit {
+                self.estimator = Some(Estimator::new(
+                    method,
+                    observations,
+                    ESTIMATE_NEIGHBORS,
+                    OBS_RADIUS,
+                ));
+                self.dirty.clear();
+                self.update_all_cost();
+                return;
+            }
+            if let Some(estimator) = &mut self.estimator {
+                estimator.set_observations(observations);
+            }
+        }
+        let mut done = Grid::new(false);
+        for o in std::mem::take(&mut self.dirty) {
+            let r = OBS_RADIUS as i64;
+            for dx in -r..=r {
+                let r = r - dx.abs();
+                for dy in -r..=r {
+                    let x = o.x() as i64 + dx;
+                    let y = o.y() as i64 + dy;
+                    if x < 0 || y < 0 || N as i64 <= x || N as i64 <= y {
+                        continue;
+                    }
+                    let p = Point::new(x as u32, y as u32);
+                    if !done[p] {
+                        done[p] = true;
+                        self.update_cell_cost(p);
                     }
-                    heap.push(DState { p: n, w });
                 }
             }
         }
-        unreachable!()
+        self.update_cost_range();
+    }
+
+    pub fn observe(&mut self, p: Point, s: u32, power: u32, broke: bool) {
+        self.ensemble.observe(p, s, power, broke);
+        self.damage[p] = s + power;
+        if broke {
+            self.network.add(p);
+        }
+        self.dirty.push(p);
+    }
+
+    pub fn water(&self) -> &[Point] {
+        &self.water
+    }
+
+    pub fn house(&self) -> &[Point] {
+        &self.house
+    }
+
+    pub fn c(&self) -> u32 {
+        self.c
     }
 
-    pub fn solve(&mut self, rng: &mut Mcg128Xsl64) -> (Vec<Point>, u64) {
-        let mut house = self.house.clone();
-        house.shuffle(rng);
+    pub fn is_broken(&self, p: Point) -> bool {
+        self.network.is_broken(p)
+    }
 
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    pub fn damage(&self, p: Point) -> u32 {
+        self.damage[p]
+    }
+
+    // 水源と network のマスを全部始点にして、target を確定するまで field に広げる。
+    // target が None なら全マス。network のマスは距離 0、それ以外の水源はそのマスを掘るコストから始める
+    pub fn distance_field(
+        &self,
+        field: &mut DistanceField,
+        network: &[Point],
+        target: Option<Point>,
+    ) {
+        self.distance_field_from(field, network, &self.water, target);
+    }
+
+    // distance_field の水源を water に限ったもの
+    fn distance_field_from(
+        &self,
+        field: &mut DistanceField,
+        network: &[Point],
+        water: &[Point],
+        target: Option<Point>,
+    ) {
+        let sources = network
+            .iter()
+            .map(|&p| (p, 0))
+            .chain(water.iter().map(|&p| (p, self.guess_cost[p] as u64)));
+        let min_cost = if self.astar { self.min_cost } else { 0 };
+        field.run(&self.guess_cost, (min_cost, self.max_cost), sources, target);
+    }
+
+    // 家ごとに、水源 1 つだけからの距離で一番近い水源を選ぶ。
+    // 2 番目に近い水源との差 (regret) も返す。水源が 1 つなら regret は 0
+    pub fn assign_sources(&self) -> Vec<(usize, u64)> {
+        let mut field = DistanceField::new();
+        let mut dist = vec![Vec::with_capacity(self.water.len()); self.house.len()];
+        for (i, &w) in self.water.iter().enumerate() {
+            self.distance_field_from(&mut field, &[], &[w], None);
+            for (d, &h) in dist.iter_mut().zip(self.house.iter()) {
+                d.push((field.dist(h).unwrap(), i));
+            }
+        }
+        dist.into_iter()
+            .map(|mut d| {
+                d.sort();
+                let regret = if d.len() < 2 { 0 } else { d[1].0 - d[0].0 };
+                (d[0].1, regret)
+            })
+            .collect()
+    }
+
+    // 家ごとにつなぐ水源を決めてから、order の決まった順に水源ごとのネットワークを伸ばす。乱数は使わない
+    pub fn solve_assigned(&self, order: ConnectOrder) -> (Vec<Point>, u64) {
+        let assign = self.assign_sources();
+        let mut network = vec![Vec::new(); self.water.len()];
+        let mut in_network = Grid::new(false);
         let mut ans = Vec::new();
         let mut cost = 0;
-        for start in house {
-            let (target, prev) = self.dijkstra(start);
-            let mut cur = target;
-            loop {
-                if !self.grid[cur] {
-                    self.grid[cur] = true;
-                    ans.push(cur);
-                    cost += self.guess_cost[cur] as u64;
+        let mut connect = |network: &mut Vec<Point>, path: Vec<Point>| {
+            for p in path {
+                if !in_network[p] {
+                    in_network[p] = true;
+                    network.push(p);
+                    if !self.network.is_broken(p) {
+                        ans.push(p);
+                        cost += self.guess_cost[p] as u64;
+                    }
+                }
+            }
+        };
+        match order {
+            ConnectOrder::Random => return self.solve_order(&self.house),
+            // 後回しにすると損の大きい家から
+            ConnectOrder::Regret => {
+                let mut houses = (0..self.house.len()).collect::<Vec<_>>();
+                houses.sort_by_key(|&i| (std::cmp::Reverse(assign[i].1), i));
+                let mut field = DistanceField::new();
+                for i in houses {
+                    let (w, h) = (assign[i].0, self.house[i]);
+                    self.distance_field_from(&mut field, &network[w], &[self.water[w]], Some(h));
+                    connect(&mut network[w], field.path(h));
+                }
+            }
+            // 水源ごとのネットワークに一番近い家からつなぐ
+            ConnectOrder::Prim => {
+                let mut fields = (0..self.water.len())
+                    .map(|_| DistanceField::new())
+                    .collect::<Vec<_>>();
+                let mut dirty = vec![true; self.water.len()];
+                let mut rest = (0..self.house.len()).collect::<Vec<_>>();
+                while !rest.is_empty() {
+                    for &i in rest.iter() {
+                        let w = assign[i].0;
+                        if dirty[w] {
+                            dirty[w] = false;
+                            self.distance_field_from(
+                                &mut fields[w],
+                                &network[w],
+                                &[self.water[w]],
+                                None,
+                            );
+                        }
+                    }
+                    let k = (0..rest.len())
+                        .min_by_key(|&k| {
+                            let i = rest[k];
+                            (fields[assign[i].0].dist(self.house[i]), i)
+                        })
+                        .unwrap();
+                    let i = rest.swap_remove(k);
+                    let (w, h) = (assign[i].0, self.house[i]);
+                    connect(&mut network[w], fields[w].path(h));
+                    dirty[w] = true;
                 }
-                if let Some(nex) = prev[cur] {
-                    cur = nex;
-                } else {
-                    break;
+            }
+        }
+        (ans, cost)
+    }
+
+    // 家を house の順に、それまでに掘ることにしたマスか水源へつないでいく
+    pub fn solve_order(&self, house: &[Point]) -> (Vec<Point>, u64) {
+        let mut field = DistanceField::new();
+        let mut in_network = Grid::new(false);
+        let mut network = Vec::new();
+        let mut ans = Vec::new();
+        let mut cost = 0;
+        for &h in house {
+            self.distance_field(&mut field, &network, Some(h));
+            for p in field.path(h) {
+                if !in_network[p] {
+                    in_network[p] = true;
+                    network.push(p);
+                    if !self.network.is_broken(p) {
+                        ans.push(p);
+                        cost += self.guess_cost[p] as u64;
+                    }
                 }
             }
         }
         (ans, cost)
     }
 
-    pub fn guess_power(&self, p: Point, s: u32) -> (u32, u32) {
-        guess_power(&self.h, p, self.c, s)
+    // 実際に壊したマスを調べ、まだ水源につながっていない家をつなぐ掘る順を返す。全部つながっていれば空
+    pub fn repair_plan(&self) -> Vec<Point> {
+        let mut network = self.network.watered_cells();
+        let mut reach = Grid::new(false);
+        for &p in network.iter() {
+            reach[p] = true;
+        }
+        let mut field = DistanceField::new();
+        let mut ans = Vec::new();
+        for &h in self.house.iter() {
+            if reach[h] {
+                continue;
+            }
+            self.distance_field(&mut field, &network, Some(h));
+            for p in field.path(h) {
+                if !reach[p] {
+                    reach[p] = true;
+                    network.push(p);
+                    if !self.network.is_broken(p) {
+                        ans.push(p);
+                    }
+                }
+            }
+        }
+        ans
+    }
+
+    // 壊したマスはただで使えるものとして restarts 回 (最低 1 回) 解き直し、一番安い掘る順を返す。
+    // threads 個ずつ解き、1 回あたりの平均で次の組が deadline までに終わらなくなったらそこでやめる。
+    // order が Random 以外なら乱数を使わず solve_assigned で 1 回だけ解く
+    pub fn plan(
+        &self,
+        rng: &mut Mcg128Xsl64,
+        restarts: usize,
+        deadline: Instant,
+    ) -> (Vec<Point>, u64) {
+        if self.order != ConnectOrder::Random {
+            return self.solve_assigned(self.order);
+        }
+        // 打ち切っても乱数の進み方が変わらないように、順番は先に全部引いておく
+        let orders = (0..restarts.max(1))
+            .map(|_| {
+                let mut house = self.house.clone();
+                house.shuffle(rng);
+                house
+            })
+            .collect::<Vec<_>>();
+        let mut best = Vec::new();
+        let mut best_cost = std::u64::MAX;
+        let mut pace = Pace::new();
+        for (i, chunk) in orders.chunks(self.threads.max(1)).enumerate() {
+            if i > 0 && pace.affordable(deadline) < chunk.len() {
+                break;
+            }
+            let start = Instant::now();
+            for (ans, cost) in
+                parallel::map_with(self.threads, chunk, |house| self.solve_order(house))
+            {
+                if best_cost.setmin(cost) {
+                    best = ans;
+                }
+            }
+            // 1 回あたりは組の時間を数で割ったものにして、並列に解いた分も割り引く
+            let elapsed = start.elapsed() / chunk.len() as u32;
+            for _ in 0..chunk.len() {
+                pace.record(elapsed);
+            }
+        }
+        (best, best_cost)
+    }
+
+    // 乱択の代わりにシュタイナー木で一度に全部の家をつなぐ
+    pub fn steiner(&self) -> (Vec<Point>, u64) {
+        let (cells, cost) = steiner(&self.guess_cost, &self.water, &self.house);
+        let cells = cells
+            .into_iter()
+            .filter(|&p| !self.network.is_broken(p))
+            .collect();
+        (cells, cost)
+    }
+
+    // plan と壊したマスをつないだネットワークを焼きなましで改善し、水源側から掘る順に返す
+    pub fn anneal(
+        &self,
+        rng: &mut Mcg128Xsl64,
+        plan: &[Point],
+        config: &AnnealConfig,
+        deadline: Option<Instant>,
+    ) -> (Vec<Point>, u64) {
+        let mut cells = Grid::new(false);
+        for &p in plan {
+            cells[p] = true;
+        }
+        for &p in self.network.cells() {
+            cells[p] = true;
+        }
+        let cells = anneal(
+            rng,
+            &self.guess_cost,
+            &self.water,
+            &self.house,
+            cells,
+            config,
+            deadline,
+        );
+        let (order, cost) = order_from_water(&self.guess_cost, &self.water, &cells);
+        let order = order
+            .into_iter()
+            .filter(|&p| !self.network.is_broken(p))
+            .collect();
+        (order, cost)
+    }
+
+    pub fn plan_cost(&self, plan: impl Iterator<Item = Point>) -> u64 {
+        plan.map(|p| self.guess_cost[p] as u64).sum()
+    }
+
+    // 候補の重み付き平均で見積もった硬さ。候補がなければ None
+    pub fn predicted_hardness(&self, p: Point) -> Option<u32> {
+        let v = self.candidates(p);
+        let total = v.iter().map(|&(_, w)| w).sum::<f64>();
+        if total <= 0.0 {
+            return None;
+        }
+        let mean = v.iter().map(|&(h, w)| h as f64 * w).sum::<f64>() / total;
+        Some(mean.round() as u32)
+    }
+
+    // 累積パワー s で壊れていない p に次に使うパワーと期待コスト。
+    // 表のとおりに打って壊れなかったとき、s より硬い候補が表を作ったときと同じなら、
+    // DP を解き直しても同じ表の続きになるので使い回す。
+    // 候補は p の観測でアンサンブルが条件付け直したときや、近くの観測の補正で変わる
+    pub fn guess_power(&mut self, p: Point, s: u32) -> (u32, u32) {
+        let v = self
+            .candidates(p)
+            .into_iter()
+            .filter(|&(h, _)| s < h)
+            .collect::<Vec<_>>();
+        if let Some(schedule) = &self.schedule {
+            if schedule.p == p
+                && schedule.table.covers(s)
+                && schedule
+                    .candidates
+                    .iter()
+                    .filter(|&&(h, _)| s < h)
+                    .eq(v.iter())
+            {
+                return schedule.table.get(s);
+            }
+        }
+        self.schedule = PowerTable::from_candidates(&v, self.c, s).map(|table| Schedule {
+            p,
+            candidates: v,
+            table,
+        });
+        match &self.schedule {
+            Some(schedule) => schedule.table.get(s),
+            None => self.fallback(s),
+        }
+    }
+
+    // 打ち方は期待コスト最小のまま、そのときのコストの分布を risk で 1 つの値にする
+    fn risky_cost(&self, p: Point, s: u32) -> u32 {
+        if self.risk == Risk::Mean {
+            return match best_power(&self.candidates(p), self.c, s) {
+                Some((_, cost)) => cost.round() as u32,
+                None => self.fallback(s).1,
+            };
+        }
+        match schedule_costs(&self.candidates(p), self.c, s) {
+            Some((_, costs)) => self.risk.measure(&costs).round() as u32,
+            None => self.fallback(s).1,
+        }
+    }
+
+    fn fallback(&self, s: u32) -> (u32, u32) {
+        match self.fallback_power {
+            Some(power) => (power, power + self.c),
+            None => self.power.get(s),
+        }
+    }
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn test_parallel_matches_sequential() {
+    use std::time::Duration;
+
+    // 既定の設定なら、スレッドに分けても 1 本で作っても同じ高さマップと掘る順になる
+    let water = [(20, 30)];
+    let house = [(60, 40), (30, 90), (120, 150)];
+    let solve = |threads: Option<usize>, order: ConnectOrder| {
+        let config = SolverConfig {
+            threads,
+            order,
+            ..SolverConfig::default()
+        };
+        let time = TimeKeeper::new(Duration::from_millis(config.time_limit_millis));
+        let mut rng = Mcg128Xsl64::new(config.seed as u128);
+        let solver = Solver::new(&mut rng, &water, &house, 8, &config, &time);
+        assert_eq!(solver.sample_stats().timeouts, 0);
+        let plan = solver.plan(
+            &mut rng,
+            config.restarts,
+            Instant::now() + Duration::from_secs(600),
+        );
+        (solver, plan)
+    };
+    for &order in [ConnectOrder::Prim, ConnectOrder::Random].iter() {
+        let (a, plan_a) = solve(Some(1), order);
+        let (b, plan_b) = solve(Some(4), order);
+        for (ha, hb) in a.ensemble.samples().iter().zip(b.ensemble.samples()) {
+            assert!(ha.iter().eq(hb.iter()));
+        }
+        assert_eq!(plan_a, plan_b);
+    }
+}
+
+#[test]
+fn test_update_cost_matches_full() {
+    use std::time::Duration;
+
+    // 観測の周りだけ計算し直した guess_cost が、全マスを計算し直したものと一致する
+    let config = SolverConfig {
+        samples: 4,
+        max_attempts: 100,
+        ..SolverConfig::default()
+    };
+    let time = TimeKeeper::new(Duration::from_millis(config.time_limit_millis));
+    let mut rng = Mcg128Xsl64::new(1);
+    let mut solver = Solver::new(&mut rng, &[(20, 30)], &[(150, 160)], 8, &config, &time);
+    for i in 0..8 {
+        solver.observe(Point::new(20 * i + 10, 40), 0, 300, i % 2 == 0);
+    }
+    solver.update_cost();
+    for i in 0..6 {
+        let p = Point::new(30 * i + 5, 120);
+        solver.observe(p, 0, 200, false);
+        solver.observe(p, 200, 400, i % 3 == 0);
     }
+    solver.update_cost();
+    let partial = solver.guess_cost.clone();
+    solver.update_all_cost();
+    assert!(partial.iter().eq(solver.guess_cost.iter()));
 }
@@ -0,0 +1,438 @@
+use crate::{solver::DState, Grid, Point, SetMinMax, N};
+use std::collections::{BinaryHeap, VecDeque};
+
+// 家がこれ以下なら Dreyfus–Wagner で厳密に解く
+const DW_MAX_HOUSE: usize = 5;
+const IMPROVE_ROUNDS: usize = 100;
+
+// 全ての家をどこかの水源につなぐ、マス重み付きシュタイナー木。
+// 水源を 1 つにまとめた仮想的な根を端点に加えて普通のシュタイナー木として解く。
+// 返すのは水源側から掘る順に並べたマスとそのコストの合計
+pub fn steiner(cost: &Grid<u32>, water: &[Point], house: &[Point]) -> (Vec<Point>, u64) {
+    let cells = if house.len() <= DW_MAX_HOUSE {
+        dreyfus_wagner(cost, water, house)
+    } else {
+        let mut cells = shortest_path_heuristic(cost, water, house);
+        for _ in 0..IMPROVE_ROUNDS {
+            if !exchange_key_paths(cost, water, house, &mut cells) {
+                break;
+            }
+        }
+        cells
+    };
+    order_from_water(cost, water, &cells)
+}
+
+pub(crate) fn order_from_water(
+    cost: &Grid<u32>,
+    water: &[Point],
+    cells: &Grid<bool>,
+) -> (Vec<Point>, u64) {
+    let mut visited = Grid::new(false);
+    let mut queue = VecDeque::new();
+    for &w in water {
+        if cells[w] && !visited[w] {
+            visited[w] = true;
+            queue.push_back(w);
+        }
+    }
+    let mut order = Vec::new();
+    let mut total = 0;
+    while let Some(p) = queue.pop_front() {
+        order.push(p);
+        total += cost[p] as u64;
+        for n in p.neighbors() {
+            if cells[n] && !visited[n] {
+                visited[n] = true;
+                queue.push_back(n);
+            }
+        }
+    }
+    (order, total)
+}
+
+// dist の値を初期値として、隣に進むたびに進んだ先のマスのコストを足す
+fn dijkstra(cost: &Grid<u32>, dist: &mut Grid<u64>, prev: &mut Grid<Option<Point>>) {
+    let mut heap = BinaryHeap::new();
+    for y in 0..N as u32 {
+        for x in 0..N as u32 {
+            let p = Point::new(x, y);
+            if dist[p] != std::u64::MAX {
+                heap.push(DState { p, w: dist[p] });
+            }
+        }
+    }
+    while let Some(s) = heap.pop() {
+        if dist[s.p] < s.w {
+            continue;
+        }
+        for n in s.p.neighbors() {
+            let w = s.w + cost[n] as u64;
+            if dist[n].setmin(w) {
+                prev[n] = Some(s.p);
+                heap.push(DState { p: n, w });
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Back {
+    Leaf,
+    Split(usize),
+    From(Point),
+    // 仮想的な根から水源に入った
+    FromRoot,
+}
+
+// 仮想的な根は全ての水源とコスト 0 でつながった頂点として、盤面とは別に持つ
+fn dreyfus_wagner(cost: &Grid<u32>, water: &[Point], house: &[Point]) -> Grid<bool> {
+    // 端点 0 が仮想的な根、1.. が家
+    let k = house.len() + 1;
+    let full = (1 << k) - 1;
+    let mut dp = vec![Grid::new(std::u64::MAX); 1 << k];
+    let mut back = vec![Grid::new(Back::Leaf); 1 << k];
+    let mut dp_root = vec![std::u64::MAX; 1 << k];
+    let mut back_root = vec![Back::Leaf; 1 << k];
+    dp_root[1] = 0;
+    for (i, &h) in house.iter().enumerate() {
+        dp[1 << (i + 1)][h] = cost[h] as u64;
+    }
+    for s in 1..=full {
+        // 添字の小さい方だけ見れば十分
+        let mut t = (s - 1) & s;
+        while t > 0 {
+            if t < s ^ t {
+                for y in 0..N as u32 {
+                    for x in 0..N as u32 {
+                        let p = Point::new(x, y);
+                        let (a, b) = (dp[t][p], dp[s ^ t][p]);
+                        if a != std::u64::MAX && b != std::u64::MAX {
+                            let w = a + b - cost[p] as u64;
+                            if dp[s][p].setmin(w) {
+                                back[s][p] = Back::Split(t);
+                            }
+                        }
+                    }
+                }
+                let (a, b) = (dp_root[t], dp_root[s ^ t]);
+                if a != std::u64::MAX && b != std::u64::MAX && dp_root[s].setmin(a + b) {
+                    back_root[s] = Back::Split(t);
+                }
+            }
+            t = (t - 1) & s;
+        }
+
+        let mut heap = BinaryHeap::new();
+        for y in 0..N as u32 {
+            for x in 0..N as u32 {
+                let p = Point::new(x, y);
+                if dp[s][p] != std::u64::MAX {
+                    heap.push(DState { p, w: dp[s][p] });
+                }
+            }
+        }
+        let mut root_done = false;
+        loop {
+            // 根は heap に入れず、次に取り出す値より小さければ先に処理する
+            let next = heap.peek().map_or(std::u64::MAX, |s| s.w);
+            if !root_done && dp_root[s] != std::u64::MAX && dp_root[s] <= next {
+                root_done = true;
+                for &w in water {
+                    if dp[s][w].setmin(dp_root[s] + cost[w] as u64) {
+                        back[s][w] = Back::FromRoot;
+                        heap.push(DState { p: w, w: dp[s][w] });
+                    }
+                }
+                continue;
+            }
+            let st = match heap.pop() {
+                Some(st) => st,
+                None => break,
+            };
+            if dp[s][st.p] < st.w {
+                continue;
+            }
+            if water.contains(&st.p) && dp_root[s].setmin(st.w) {
+                back_root[s] = Back::From(st.p);
+            }
+            for n in st.p.neighbors() {
+                let w = st.w + cost[n] as u64;
+                if dp[s][n].setmin(w) {
+                    back[s][n] = Back::From(st.p);
+                    heap.push(DState { p: n, w });
+                }
+            }
+        }
+    }
+
+    let mut cells = Grid::new(false);
+    let mut stack = vec![(full, None)];
+    while let Some((s, p)) = stack.pop() {
+        let b = match p {
+            Some(p) => {
+                cells[p] = true;
+                back[s][p]
+            }
+            None => back_root[s],
+        };
+        match b {
+            Back::Leaf => {}
+            Back::Split(t) => {
+                stack.push((t, p));
+                stack.push((s ^ t, p));
+            }
+            Back::From(q) => stack.push((s, Some(q))),
+            Back::FromRoot => stack.push((s, None)),
+        }
+    }
+    cells
+}
+
+// ネットワークと未掘削の水源からの距離。ネットワークのマスは 0 から始まる
+fn distance_from(
+    cost: &Grid<u32>,
+    water: &[Point],
+    cells: &Grid<bool>,
+) -> (Grid<u64>, Grid<Option<Point>>) {
+    let mut dist = Grid::new(std::u64::MAX);
+    let mut prev = Grid::new(None);
+    for &w in water {
+        dist[w] = cost[w] as u64;
+    }
+    for (p, _) in cells.iter().filter(|&(_, &b)| b) {
+        dist[p] = 0;
+    }
+    dijkstra(cost, &mut dist, &mut prev);
+    (dist, prev)
+}
+
+fn add_path(cells: &mut Grid<bool>, prev: &Grid<Option<Point>>, from: Point) {
+    let mut cur = from;
+    loop {
+        cells[cur] = true;
+        match prev[cur] {
+            Some(q) if !cells[q] => cur = q,
+            Some(q) => {
+                cells[q] = true;
+                break;
+            }
+            None => break,
+        }
+    }
+}
+
+// 今の木に一番近い家から順につないでいく
+fn shortest_path_heuristic(cost: &Grid<u32>, water: &[Point], house: &[Point]) -> Grid<bool> {
+    let mut cells = Grid::new(false);
+    let mut rest = house.to_vec();
+    while !rest.is_empty() {
+        let (dist, prev) = distance_from(cost, water, &cells);
+        let i = (0..rest.len()).min_by_key(|&i| dist[rest[i]]).unwrap();
+        let h = rest.swap_remove(i);
+        add_path(&mut cells, &prev, h);
+    }
+    cells
+}
+
+// 水源から木を BFS で張り、端点 (家・水源・分岐点) 同士を結ぶ道 (key path) を
+// 一本ずつ外しては最短路でつなぎ直す。良くなったら木の形が変わるのでそこで true を返す
+fn exchange_key_paths(
+    cost: &Grid<u32>,
+    water: &[Point],
+    house: &[Point],
+    cells: &mut Grid<bool>,
+) -> bool {
+    let mut is_house = Grid::new(false);
+    for &h in house {
+        is_house[h] = true;
+    }
+
+    let mut parent = Grid::new(None);
+    let mut children = Grid::new(0u32);
+    let mut visited = Grid::new(false);
+    let mut order = Vec::new();
+    let mut queue = VecDeque::new();
+    for &w in water {
+        if cells[w] && !visited[w] {
+            visited[w] = true;
+            queue.push_back(w);
+        }
+    }
+    while let Some(p) = queue.pop_front() {
+        order.push(p);
+        for n in p.neighbors() {
+            if cells[n] && !visited[n] {
+                visited[n] = true;
+                parent[n] = Some(p);
+                children[p] += 1;
+                queue.push_back(n);
+            }
+        }
+    }
+
+    let is_key = |p: Point| is_house[p] || parent[p].is_none() || children[p] >= 2;
+    for &bottom in order.iter().rev() {
+        if !is_key(bottom) || parent[bottom].is_none() {
+            continue;
+        }
+        let mut path = Vec::new();
+        let mut cur = parent[bottom].unwrap();
+        while !is_key(cur) {
+            path.push(cur);
+            cur = parent[cur].unwrap();
+        }
+        if path.is_empty() {
+            continue;
+        }
+        let old_cost = path.iter().map(|&p| cost[p] as u64).sum::<u64>();
+
+        // bottom の部分木を B、それ以外を A とする
+        let mut in_b = Grid::new(false);
+        let mut b = vec![bottom];
+        in_b[bottom] = true;
+        let mut i = 0;
+        while i < b.len() {
+            let p = b[i];
+            i += 1;
+            for n in p.neighbors() {
+                if cells[n] && !in_b[n] && parent[n] == Some(p) {
+                    in_b[n] = true;
+                    b.push(n);
+                }
+            }
+        }
+        for &p in &path {
+            cells[p] = false;
+        }
+
+        let mut dist = Grid::new(std::u64::MAX);
+        let mut prev = Grid::new(None);
+        for &p in &b {
+            dist[p] = 0;
+        }
+        let mut heap = BinaryHeap::new();
+        for &p in &b {
+            heap.push(DState { p, w: 0 });
+        }
+        let mut found = None;
+        while let Some(s) = heap.pop() {
+            if dist[s.p] < s.w {
+                continue;
+            }
+            if !in_b[s.p] && (cells[s.p] || water.contains(&s.p)) {
+                found = Some(s.p);
+                break;
+            }
+            if old_cost <= s.w {
+                break;
+            }
+            for n in s.p.neighbors() {
+                let w = s.w + if cells[n] { 0 } else { cost[n] as u64 };
+                if dist[n].setmin(w) {
+                    prev[n] = Some(s.p);
+                    heap.push(DState { p: n, w });
+                }
+            }
+        }
+        match found {
+            Some(t) if dist[t] < old_cost => {
+                let mut cur = t;
+                while !in_b[cur] {
+                    cells[cur] = true;
+                    cur = prev[cur].unwrap();
+                }
+                prune(water, house, cells);
+                return true;
+            }
+            _ => {
+                for &p in &path {
+                    cells[p] = true;
+                }
+            }
+        }
+    }
+    false
+}
+
+// 家でも水源でもない葉を落とし、家を含まない連結成分を消す
+pub(crate) fn prune(water: &[Point], house: &[Point], cells: &mut Grid<bool>) {
+    let mut keep = Grid::new(false);
+    for &p in water.iter().chain(house.iter()) {
+        keep[p] = true;
+    }
+    let degree = |cells: &Grid<bool>, p: Point| p.neighbors().filter(|&n| cells[n]).count();
+    let mut stack = cells
+        .iter()
+        .filter(|&(_, &b)| b)
+        .map(|(p, _)| p)
+        .collect::<Vec<_>>();
+    while let Some(p) = stack.pop() {
+        if cells[p] && !keep[p] && degree(cells, p) <= 1 {
+            cells[p] = false;
+            stack.extend(p.neighbors().filter(|&n| cells[n]));
+        }
+    }
+
+    let mut visited = Grid::new(false);
+    let list = cells
+        .iter()
+        .filter(|&(_, &b)| b)
+        .map(|(p, _)| p)
+        .collect::<Vec<_>>();
+    for p in list {
+        if visited[p] {
+            continue;
+        }
+        visited[p] = true;
+        let mut comp = vec![p];
+        let mut i = 0;
+        while i < comp.len() {
+            let q = comp[i];
+            i += 1;
+            for n in q.neighbors() {
+                if cells[n] && !visited[n] {
+                    visited[n] = true;
+                    comp.push(n);
+                }
+            }
+        }
+        if !house.iter().any(|h| comp.contains(h)) {
+            for q in comp {
+                cells[q] = false;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_steiner() {
+    use rand::Rng;
+    use rand_pcg::Mcg128Xsl64;
+    let mut rng = Mcg128Xsl64::new(1);
+    let mut cost = Grid::new(0);
+    for y in 0..N as u32 {
+        for x in 0..N as u32 {
+            cost[Point::new(x, y)] = rng.gen_range(1..100);
+        }
+    }
+    let water = vec![
+        Point::new(10, 10),
+        Point::new(150, 20),
+        Point::new(190, 190),
+    ];
+    let house = vec![
+        Point::new(100, 100),
+        Point::new(30, 180),
+        Point::new(180, 60),
+    ];
+    let (order, dw) = order_from_water(&cost, &water, &dreyfus_wagner(&cost, &water, &house));
+    assert!(house.iter().all(|h| order.contains(h)));
+    let (order, sph) = order_from_water(
+        &cost,
+        &water,
+        &shortest_path_heuristic(&cost, &water, &house),
+    );
+    assert!(house.iter().all(|h| order.contains(h)));
+    assert!(dw <= sph);
+}
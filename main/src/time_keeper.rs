@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+// 掘削 1 回の往復時間をまだ測れていないときの見積もり
+const DEFAULT_LATENCY: Duration = Duration::from_micros(200);
+// 期限ぎりぎりまで使わないように残しておく時間
+const MARGIN: Duration = Duration::from_millis(100);
+
+// 実行時間の制限に対して、各処理がどこまで時間を使ってよいかを決める
+#[derive(Debug, Clone)]
+pub struct TimeKeeper {
+    start: Instant,
+    limit: Duration,
+}
+
+impl TimeKeeper {
+    pub fn new(limit: Duration) -> TimeKeeper {
+        TimeKeeper {
+            start: Instant::now(),
+            limit,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.limit
+            .checked_sub(self.elapsed())
+            .unwrap_or_else(|| Duration::from_secs(0))
+    }
+
+    // 制限時間全体のうち share の割合が過ぎる時刻
+    pub fn at(&self, share: f64) -> Instant {
+        self.start + self.limit.mul_f64(share.max(0.0).min(1.0))
+    }
+
+    // 残り digs 回の掘削の時間を取っておいたうえで、他の処理に使ってよい期限
+    pub fn reserve(&self, digs: usize, latency: Option<Duration>) -> Instant {
+        let latency = latency.unwrap_or(DEFAULT_LATENCY);
+        let keep = latency * digs as u32 + MARGIN;
+        match self.limit.checked_sub(keep) {
+            Some(t) => self.start + t,
+            None => self.start,
+        }
+    }
+}
+
+// 同じ処理を繰り返すときの 1 回あたりの時間を測り、期限までにあと何回できるかを見積もる
+#[derive(Debug, Clone, Default)]
+pub struct Pace {
+    runs: u32,
+    total: Duration,
+}
+
+impl Pace {
+    pub fn new() -> Pace {
+        Pace::default()
+    }
+
+    pub fn record(&mut self, elapsed: Duration) {
+        self.runs += 1;
+        self.total += elapsed;
+    }
+
+    // これまでの平均どおりにかかるとして deadline までに収まる回数。
+    // まだ測っていなければ、期限前なら 1 回だけ試させる
+    pub fn affordable(&self, deadline: Instant) -> usize {
+        let now = Instant::now();
+        if deadline <= now {
+            return 0;
+        }
+        if self.runs == 0 {
+            return 1;
+        }
+        let average = (self.total / self.runs).as_secs_f64();
+        if average <= 0.0 {
+            return std::usize::MAX;
+        }
+        ((deadline - now).as_secs_f64() / average) as usize
+    }
+}